@@ -0,0 +1,399 @@
+//! Building plugins from code.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+/// Object ID of the first record of a new plugin.
+const FIRST_OBJECT_ID: u32 = 0x800;
+
+/// Builds a `Plugin` record by record, to be written with `EsmWriter`.
+///
+/// ```ignore
+/// let mut builder = PluginBuilder::new(Game::Fallout4);
+/// builder.add_master("Fallout4.esm", 0)?;
+/// let misc = builder.add_record(b"MISC", vec![edid])?;
+/// let cell = builder.add_interior_cell(vec![])?;
+/// builder.add_reference(cell, b"REFR", false, vec![name])?;
+/// EsmWriter::new(file).write_plugin(&builder.build())?;
+/// ```
+pub struct PluginBuilder {
+    header: FileHeader,
+    groups: Vec<(GroupHeader, TopGroup<'static>)>
+}
+
+impl PluginBuilder {
+    /// An empty plugin, with new object IDs starting at 0x800.
+    pub fn new(game: Game) -> Self {
+        let header = FileHeader {
+            game,
+            version: game.hedr_version(),
+            next_object_id: FIRST_OBJECT_ID,
+            author: Some("DEFAULT".to_owned()),
+            ..Default::default()
+        };
+        PluginBuilder { header, groups: Vec::new() }
+    }
+
+    /// Set the TES4 flags, such as master or light master.
+    pub fn flags(&mut self, flags: FileFlags) -> &mut Self {
+        self.header.flags = flags;
+        self
+    }
+
+    pub fn author(&mut self, author: &str) -> &mut Self {
+        self.header.author = Some(author.to_owned());
+        self
+    }
+
+    pub fn description(&mut self, description: &str) -> &mut Self {
+        self.header.description = Some(description.to_owned());
+        self
+    }
+
+    /// Add a master, which must happen before any form IDs are allocated or
+    /// records inserted, as it changes the file index of the plugin's own records.
+    pub fn add_master(&mut self, name: &str, size: u64) -> Result<&mut Self> {
+        if self.header.next_object_id != FIRST_OBJECT_ID || !self.groups.is_empty() {
+            return Err(Error::invalid(format!("master {} added after records", name)))
+        }
+        self.header.masters.push(Master { name: name.to_owned(), size });
+        Ok(self)
+    }
+
+    /// A new form ID from the HEDR next object ID, in the plugin's own file index.
+    pub fn allocate_form_id(&mut self) -> Result<u32> {
+        allocate_form_id(&mut self.header)
+    }
+
+    //--------------------------------------------------------------------------
+
+    /// Add a record with a new form ID to the top group of its type.
+    ///
+    /// Cells, worldspaces and the records placed in cells have their own methods.
+    pub fn add_record(&mut self, type_id: &[u8; 4], fields: Vec<Field<'static>>) -> Result<u32> {
+        let form_id = self.allocate_form_id()?;
+        self.insert_record(self.record(type_id, form_id, fields))?;
+        Ok(form_id)
+    }
+
+    /// Add a record with an existing form ID, such as an override of a master's record.
+    pub fn insert_record(&mut self, record: Record<'static>) -> Result<()> {
+        let type_id = record.header.type_id;
+        match &type_id.0 {
+            b"CELL" | b"WRLD" | b"REFR" | b"ACHR" | b"ACRE" | b"LAND" | b"NAVM" | b"PGRE" | b"INFO" => {
+                return Err(Error::invalid(format!("{} record outside its parent group", type_id)))
+            },
+            b"GMST" => {
+                let setting = GameSetting::from_record(record, self.header.flags.is_localised_for(self.header.game));
+                if let TopGroup::GameSetting(settings) = self.top_group(b"GMST", || TopGroup::GameSetting(Vec::new())) {
+                    settings.push(setting);
+                }
+            },
+            b"KYWD" => {
+                if let TopGroup::Keyword(keywords) = self.top_group(b"KYWD", || TopGroup::Keyword(Vec::new())) {
+                    keywords.push(Keyword::from_record(record));
+                }
+            },
+            b"DIAL" => {
+                if let TopGroup::Dialogue(dialogs) = self.top_group(b"DIAL", || TopGroup::Dialogue(Vec::new())) {
+                    dialogs.push(Dialog { dialog: record, children: None });
+                }
+            },
+            b"QUST" => {
+                let data = QuestData::decode(&record, self.header.flags.is_localised_for(self.header.game));
+                if let TopGroup::Quest(quests) = self.top_group(b"QUST", || TopGroup::Quest(Vec::new())) {
+                    quests.push(Quest { quest: record, data, children: None });
+                }
+            },
+            _ => {
+                if let TopGroup::Unhandled(records) = self.top_group(&type_id.0, || TopGroup::Unhandled(Vec::new())) {
+                    records.push(record);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add an interior cell to the block and sub-block given by the last two digits of its object ID.
+    pub fn add_interior_cell(&mut self, fields: Vec<Field<'static>>) -> Result<u32> {
+        let form_id = self.allocate_form_id()?;
+        let object_id = (form_id & 0x00FFFFFF) as i32;
+        let (index, sub_index) = (object_id % 10, object_id / 10 % 10);
+        let cell = Cell { cell: self.record(b"CELL", form_id, fields), cell_children: None };
+
+        let TopGroup::Cell(blocks) = self.top_group(b"CELL", || TopGroup::Cell(Vec::new())) else { unreachable!() };
+        let block = match blocks.iter().position(|block| block.index == index) {
+            Some(i) => &mut blocks[i],
+            None => {
+                blocks.push(InteriorCellBlock { header: group_header(index.to_le_bytes(), 2), index, sub_blocks: Vec::new() });
+                blocks.last_mut().unwrap()
+            }
+        };
+        let sub_block = match block.sub_blocks.iter().position(|sub_block| sub_block.index == sub_index) {
+            Some(i) => &mut block.sub_blocks[i],
+            None => {
+                block.sub_blocks.push(InteriorCellSubBlock { header: group_header(sub_index.to_le_bytes(), 3), index: sub_index, cells: Vec::new() });
+                block.sub_blocks.last_mut().unwrap()
+            }
+        };
+        sub_block.cells.push(cell);
+        Ok(form_id)
+    }
+
+    /// Add a worldspace.
+    pub fn add_worldspace(&mut self, fields: Vec<Field<'static>>) -> Result<u32> {
+        let form_id = self.allocate_form_id()?;
+        let world = self.record(b"WRLD", form_id, fields);
+        if let TopGroup::Worldspace(worlds) = self.top_group(b"WRLD", || TopGroup::Worldspace(Vec::new())) {
+            worlds.push(WorldEntry { world, world_children: None });
+        }
+        Ok(form_id)
+    }
+
+    /// Add an exterior cell at grid position (x, y) of a worldspace, in the
+    /// block (32x32 cells) and sub-block (8x8 cells) containing it.
+    ///
+    /// An XCLC grid field is added if `fields` has none.
+    pub fn add_exterior_cell(&mut self, world: u32, x: i32, y: i32, mut fields: Vec<Field<'static>>) -> Result<u32> {
+        if !fields.iter().any(|field| field.type_id == b"XCLC") {
+            let mut grid = [x.to_le_bytes(), y.to_le_bytes()].concat();
+            if !matches!(self.header.game, Game::Oblivion | Game::Fallout3 | Game::FalloutNV) { grid.extend([0; 4]); }
+            fields.push(Field { type_id: FourCC(*b"XCLC"), data: grid.into() });
+        }
+        // check the world before taking a form ID
+        self.world_children(world)?;
+        let form_id = self.allocate_form_id()?;
+        let cell = Cell { cell: self.record(b"CELL", form_id, fields), cell_children: None };
+
+        let children = self.world_children(world)?;
+        let (block_x, block_y) = (x.div_euclid(32) as i16, y.div_euclid(32) as i16);
+        let (sub_x, sub_y) = (x.div_euclid(8) as i16, y.div_euclid(8) as i16);
+        let block = match children.blocks.iter().position(|block| (block.x, block.y) == (block_x, block_y)) {
+            Some(i) => &mut children.blocks[i],
+            None => {
+                let header = group_header(grid_label(block_x, block_y), 4);
+                children.blocks.push(ExteriorCellBlock { header, x: block_x, y: block_y, sub_blocks: Vec::new() });
+                children.blocks.last_mut().unwrap()
+            }
+        };
+        let sub_block = match block.sub_blocks.iter().position(|sub_block| (sub_block.x, sub_block.y) == (sub_x, sub_y)) {
+            Some(i) => &mut block.sub_blocks[i],
+            None => {
+                let header = group_header(grid_label(sub_x, sub_y), 5);
+                block.sub_blocks.push(ExteriorCellSubBlock { header, x: sub_x, y: sub_y, cells: Vec::new() });
+                block.sub_blocks.last_mut().unwrap()
+            }
+        };
+        sub_block.cells.push(cell);
+        Ok(form_id)
+    }
+
+    /// Add the persistent cell of a worldspace, which holds its persistent references.
+    pub fn add_persistent_cell(&mut self, world: u32, fields: Vec<Field<'static>>) -> Result<u32> {
+        if self.world_children(world)?.cell.is_some() {
+            return Err(Error::invalid(format!("second persistent cell in worldspace {:08X}", world)))
+        }
+        let form_id = self.allocate_form_id()?;
+        let cell = Cell { cell: self.record(b"CELL", form_id, fields), cell_children: None };
+        self.world_children(world)?.cell = Some(cell);
+        Ok(form_id)
+    }
+
+    /// Add a placed object (REFR, ACHR, ...) to the persistent or temporary children of a cell.
+    pub fn add_reference(&mut self, cell: u32, type_id: &[u8; 4], persistent: bool, fields: Vec<Field<'static>>) -> Result<u32> {
+        self.add_cell_child(cell, if persistent { 8 } else { 9 }, type_id, fields)
+    }
+
+    /// Add a placed object to the visible distant children of a cell (Oblivion to Fallout: New Vegas).
+    pub fn add_visible_distant_reference(&mut self, cell: u32, type_id: &[u8; 4], fields: Vec<Field<'static>>) -> Result<u32> {
+        self.add_cell_child(cell, 10, type_id, fields)
+    }
+
+    /// Add the landscape of an exterior cell, stored with its temporary children.
+    pub fn add_landscape(&mut self, cell: u32, fields: Vec<Field<'static>>) -> Result<u32> {
+        self.add_cell_child(cell, 9, b"LAND", fields)
+    }
+
+    /// Add an INFO to the topic children of a dialogue topic added with `add_record`.
+    pub fn add_topic_info(&mut self, dialog: u32, fields: Vec<Field<'static>>) -> Result<u32> {
+        let exists = self.groups.iter().any(|(_, group)| match group {
+            TopGroup::Dialogue(dialogs) => dialogs.iter().any(|d| d.dialog.form_id() == dialog),
+            _ => false
+        });
+        if !exists { return Err(Error::invalid(format!("no dialogue topic {:08X}", dialog))) }
+
+        let form_id = self.allocate_form_id()?;
+        let info = self.record(b"INFO", form_id, fields);
+        for (_, group) in &mut self.groups {
+            let TopGroup::Dialogue(dialogs) = group else { continue };
+            let Some(topic) = dialogs.iter_mut().find(|d| d.dialog.form_id() == dialog) else { continue };
+            let children = topic.children.get_or_insert_with(|| Group { header: group_header(dialog.to_le_bytes(), 7), children: Vec::new() });
+            children.children.push(GroupChild::Record(info));
+            break
+        }
+        Ok(form_id)
+    }
+
+    //--------------------------------------------------------------------------
+
+    /// The finished plugin, with the HEDR record count and next object ID filled in.
+    pub fn build(self) -> Plugin<'static> {
+        let PluginBuilder { mut header, groups } = self;
+        header.num_records = groups.iter().map(|(_, group)| count_records(group)).sum();
+
+        let mut hedr = Vec::new();
+        hedr.extend(header.version.to_le_bytes());
+        hedr.extend(header.num_records.to_le_bytes());
+        hedr.extend(header.next_object_id.to_le_bytes());
+        let mut fields = vec![Field { type_id: FourCC(*b"HEDR"), data: hedr.into() }];
+        let zstring = |type_id: &[u8; 4], text: &str| Field { type_id: FourCC(*type_id), data: [text.as_bytes(), &[0]].concat().into() };
+        if let Some(author) = &header.author { fields.push(zstring(b"CNAM", author)); }
+        if let Some(description) = &header.description { fields.push(zstring(b"SNAM", description)); }
+        for master in &header.masters {
+            fields.push(zstring(b"MAST", &master.name));
+            fields.push(Field { type_id: FourCC(*b"DATA"), data: master.size.to_le_bytes().to_vec().into() });
+        }
+
+        let mut tes4 = record_header(header.game, b"TES4", 0);
+        tes4.flags = header.flags.0;
+        Plugin { header: Record { header: tes4, fields, compressed: None }, file_header: header, groups }
+    }
+
+    //--------------------------------------------------------------------------
+
+    fn record(&self, type_id: &[u8; 4], form_id: u32, fields: Vec<Field<'static>>) -> Record<'static> {
+        Record { header: record_header(self.header.game, type_id, form_id), fields, compressed: None }
+    }
+
+    /// The top group of a record type, added if it does not exist yet.
+    fn top_group(&mut self, type_id: &[u8; 4], new: impl FnOnce() -> TopGroup<'static>) -> &mut TopGroup<'static> {
+        let i = match self.groups.iter().position(|(header, _)| &group_label(header) == type_id) {
+            Some(i) => i,
+            None => {
+                self.groups.push((group_header(*type_id, 0), new()));
+                self.groups.len() - 1
+            }
+        };
+        &mut self.groups[i].1
+    }
+
+    fn world_children(&mut self, world: u32) -> Result<&mut WorldChildren<'static>> {
+        let entry = self.groups.iter_mut().find_map(|(_, group)| match group {
+            TopGroup::Worldspace(worlds) => worlds.iter_mut().find(|entry| entry.world.form_id() == world),
+            _ => None
+        });
+        let entry = entry.ok_or_else(|| Error::invalid(format!("no worldspace {:08X}", world)))?;
+        Ok(entry.world_children.get_or_insert_with(|| WorldChildren {
+            header: group_header(world.to_le_bytes(), 1), road: None, cell: None, blocks: Vec::new()
+        }))
+    }
+
+    /// Add a record to the children of a cell, allocating its form ID once the cell is found.
+    fn add_cell_child(&mut self, cell: u32, group_type: u32, type_id: &[u8; 4], fields: Vec<Field<'static>>) -> Result<u32> {
+        let mut found = None;
+        for (_, group) in &mut self.groups {
+            match group {
+                TopGroup::Cell(blocks) => found = found.or(blocks.iter_mut()
+                    .flat_map(|block| &mut block.sub_blocks)
+                    .flat_map(|sub_block| &mut sub_block.cells)
+                    .find(|c| c.cell.form_id() == cell)),
+                TopGroup::Worldspace(worlds) => for children in worlds.iter_mut().filter_map(|entry| entry.world_children.as_mut()) {
+                    found = found.or(children.cell.iter_mut()
+                        .chain(children.blocks.iter_mut()
+                            .flat_map(|block| &mut block.sub_blocks)
+                            .flat_map(|sub_block| &mut sub_block.cells))
+                        .find(|c| c.cell.form_id() == cell));
+                },
+                _ => {}
+            }
+        }
+        let found = found.ok_or_else(|| Error::invalid(format!("no cell {:08X}", cell)))?;
+        let form_id = allocate_form_id(&mut self.header)?;
+        let record = Record { header: record_header(self.header.game, type_id, form_id), fields, compressed: None };
+
+        let children = found.cell_children.get_or_insert_with(|| CellChildren {
+            parent_id: cell,
+            header: group_header(cell.to_le_bytes(), 6),
+            temporary: None, persistant: None, visible_distant: None,
+            subgroups: Vec::new()
+        });
+        let slot = match group_type {
+            8 => &mut children.persistant,
+            9 => &mut children.temporary,
+            _ => &mut children.visible_distant
+        };
+        if slot.is_none() {
+            // persistent children come first
+            let subgroup = group_header(cell.to_le_bytes(), group_type);
+            if group_type == 8 { children.subgroups.insert(0, subgroup) } else { children.subgroups.push(subgroup) }
+        }
+        slot.get_or_insert_with(Vec::new).push(record);
+        Ok(form_id)
+    }
+}
+
+//------------------------------------------------------------------------------
+
+/// Take the next object ID, checking it fits the plugin's FormID range.
+fn allocate_form_id(header: &mut FileHeader) -> Result<u32> {
+    let object_id = header.next_object_id;
+    let kind = header.master_kind();
+    if object_id > kind.max_object_id() {
+        return Err(Error::invalid(format!("object ID {:#x} past {:#x}", object_id, kind.max_object_id())))
+    }
+    header.next_object_id += 1;
+    Ok((header.masters.len() as u32) << 24 | object_id)
+}
+
+/// Record header for a game, with zero size and flags.
+fn record_header(game: Game, type_id: &[u8; 4], form_id: u32) -> RecordHeader {
+    let mut bytes = [0u8; std::mem::size_of::<RecordHeader>()];
+    bytes[0..4].copy_from_slice(type_id);
+    bytes[12..16].copy_from_slice(&form_id.to_le_bytes());
+    bytes[20..22].copy_from_slice(&game.form_version().to_le_bytes());
+    unsafe { std::mem::transmute(bytes) }
+}
+
+/// Group header with zero size, which `EsmWriter` fills in.
+fn group_header(label: [u8; 4], group_type: u32) -> GroupHeader {
+    let mut bytes = [0u8; std::mem::size_of::<GroupHeader>()];
+    bytes[0..4].copy_from_slice(b"GRUP");
+    bytes[8..12].copy_from_slice(&label);
+    bytes[12..16].copy_from_slice(&group_type.to_le_bytes());
+    unsafe { std::mem::transmute(bytes) }
+}
+
+/// Exterior block label, Y then X.
+fn grid_label(x: i16, y: i16) -> [u8; 4] {
+    let (y, x) = (y.to_le_bytes(), x.to_le_bytes());
+    [y[0], y[1], x[0], x[1]]
+}
+
+/// Number of records and groups in a top group, including itself, as counted by HEDR.
+fn count_records(group: &TopGroup) -> u32 {
+    let cell_groups = |cell: &Cell| cell.cell_children.as_ref().map_or(0, |children| 1 + children.subgroups.len() as u32);
+    let tree_groups = |group: &Group| {
+        fn count(group: &Group) -> u32 {
+            1 + group.children.iter().map(|child| match child {
+                GroupChild::Group(group) => count(group),
+                GroupChild::Record(_) => 0
+            }).sum::<u32>()
+        }
+        count(group)
+    };
+    let groups: u32 = match group {
+        TopGroup::Worldspace(worlds) => worlds.iter().filter_map(|entry| entry.world_children.as_ref()).map(|children| {
+            1 + children.cell.as_ref().map_or(0, cell_groups) + children.blocks.iter().map(|block| {
+                1 + block.sub_blocks.iter().map(|sub_block| 1 + sub_block.cells.iter().map(cell_groups).sum::<u32>()).sum::<u32>()
+            }).sum::<u32>()
+        }).sum(),
+        TopGroup::Cell(blocks) => blocks.iter().map(|block| {
+            1 + block.sub_blocks.iter().map(|sub_block| 1 + sub_block.cells.iter().map(cell_groups).sum::<u32>()).sum::<u32>()
+        }).sum(),
+        TopGroup::Dialogue(dialogs) => dialogs.iter().filter_map(|dialog| dialog.children.as_ref()).map(tree_groups).sum(),
+        TopGroup::Quest(quests) => quests.iter().filter_map(|quest| quest.children.as_ref()).map(tree_groups).sum(),
+        _ => 0
+    };
+    1 + groups + group.records().len() as u32
+}
@@ -0,0 +1,248 @@
+//! Sidecar cache of the FormID and EditorID index.
+//!
+//! The index of `<plugin>` is stored next to it as `<plugin>.idx`, together
+//! with the plugin size, modification time and a hash of both ends of the file. A cache whose
+//! key no longer matches the plugin is rebuilt.
+
+use crate::*;
+
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Seek};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+//------------------------------------------------------------------------------
+
+const MAGIC: &[u8; 8] = b"ESMIDX\0\0";
+const VERSION: u32 = 3;
+
+/// Bytes hashed at each end of a plugin.
+const HASH_SPAN: u64 = 64 << 10;
+
+/// Identity of a plugin file, compared to decide whether a cache is valid.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheKey {
+    pub size: u64,
+    /// Modification time in nanoseconds since the Unix epoch.
+    pub modified: u128,
+    /// FNV-1a hash of the first and last `HASH_SPAN` bytes of the plugin.
+    pub hash: u64
+}
+
+impl CacheKey {
+    /// Key of a plugin file.
+    ///
+    /// Only the ends of the file are hashed, so the key costs the same for any
+    /// plugin size. The start holds the TES4 header, whose record count and
+    /// next object ID change with most edits, and together with the size and
+    /// modification time this catches a plugin being replaced.
+    pub fn of(path: impl AsRef<Path>) -> Result<CacheKey> {
+        let mut file = File::open(path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |time| time.as_nanos());
+
+        let mut hash = Fnv1a::default();
+        let mut buffer = Vec::new();
+        file.by_ref().take(HASH_SPAN).read_to_end(&mut buffer)?;
+        hash.write(&buffer);
+        if size > HASH_SPAN {
+            let tail = HASH_SPAN.min(size - HASH_SPAN);
+            file.seek(std::io::SeekFrom::Start(size - tail))?;
+            buffer.clear();
+            file.take(tail).read_to_end(&mut buffer)?;
+            hash.write(&buffer);
+        }
+
+        Ok(CacheKey { size, modified, hash: hash.0 })
+    }
+}
+
+/// 64 bit FNV-1a over little endian words, stable across builds unlike the std hasher.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self { Fnv1a(0xcbf29ce484222325) }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut words = bytes.chunks_exact(8);
+        for word in &mut words {
+            let word = u64::from_le_bytes([word[0], word[1], word[2], word[3], word[4], word[5], word[6], word[7]]);
+            self.0 = (self.0 ^ word).wrapping_mul(0x100000001b3);
+        }
+        for &byte in words.remainder() {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// Code a game is stored as, independent of the order of `Game`'s variants.
+fn game_code(game: Game) -> u8 {
+    match game {
+        Game::Oblivion => 0,
+        Game::Fallout3 => 1,
+        Game::FalloutNV => 2,
+        Game::SkyrimLE => 3,
+        Game::SkyrimSE => 4,
+        Game::Fallout4 => 5,
+        Game::Starfield => 6
+    }
+}
+
+fn game_from_code(code: u8) -> Option<Game> {
+    [Game::Oblivion, Game::Fallout3, Game::FalloutNV, Game::SkyrimLE, Game::SkyrimSE, Game::Fallout4, Game::Starfield]
+        .into_iter()
+        .find(|&game| game_code(game) == code)
+}
+
+/// Longest EditorID accepted from a cache, which also bounds what a corrupt length can allocate.
+const MAX_EDITOR_ID: u32 = 4096;
+
+//------------------------------------------------------------------------------
+
+/// Path of the sidecar cache of a plugin.
+pub fn cache_path(plugin: impl AsRef<Path>) -> PathBuf {
+    let mut path = plugin.as_ref().as_os_str().to_owned();
+    path.push(".idx");
+    PathBuf::from(path)
+}
+
+impl FormIdIndex {
+    /// Load the index of a plugin from its cache, or scan the plugin and
+    /// write a new cache if there is none or it is out of date.
+    ///
+    /// Failing to write the cache (a read-only data directory, say) is not an error.
+    pub fn load_or_scan(plugin: impl AsRef<Path>) -> Result<FormIdIndex> {
+        let plugin = plugin.as_ref();
+        let key = CacheKey::of(plugin)?;
+        let cache = cache_path(plugin);
+
+        if let Ok(file) = File::open(&cache) {
+            if let Ok((cached_key, index)) = FormIdIndex::read_cache(&mut BufReader::new(file)) {
+                if cached_key == key { return Ok(index) }
+            }
+        }
+
+        let mut parser = ESMParser2::new(BufReader::new(File::open(plugin)?));
+        let index = parser.scan_editor_ids()?;
+        if let Ok(file) = File::create(&cache) {
+            let mut writer = BufWriter::new(file);
+            let written = index.write_cache(&key, &mut writer).and_then(|_| writer.flush().map_err(Error::from));
+            if written.is_err() {
+                let _ = std::fs::remove_file(&cache);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Write the index and the key of the plugin it was built from.
+    ///
+    /// Group types, offsets and form IDs are little endian, and the game is
+    /// stored as a fixed code so the format does not depend on `Game`.
+    pub fn write_cache<W: Write>(&self, key: &CacheKey, w: &mut W) -> Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&key.size.to_le_bytes())?;
+        w.write_all(&key.modified.to_le_bytes())?;
+        w.write_all(&key.hash.to_le_bytes())?;
+        w.write_all(&[game_code(self.game)])?;
+
+        w.write_all(&(self.paths.len() as u32).to_le_bytes())?;
+        for path in &self.paths {
+            w.write_all(&(path.len() as u32).to_le_bytes())?;
+            for group in path {
+                w.write_all(&group.group_type.to_le_bytes())?;
+                w.write_all(&group.label)?;
+            }
+        }
+
+        w.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        for (form_id, entry) in &self.entries {
+            w.write_all(&form_id.to_le_bytes())?;
+            w.write_all(&entry.type_id.0)?;
+            w.write_all(&entry.offset.to_le_bytes())?;
+            w.write_all(&entry.size.to_le_bytes())?;
+            w.write_all(&[entry.compressed as u8])?;
+            w.write_all(&entry.group.to_le_bytes())?;
+        }
+
+        w.write_all(&(self.editor_ids.len() as u32).to_le_bytes())?;
+        for (editor_id, form_id) in &self.editor_ids {
+            w.write_all(&(editor_id.len() as u32).to_le_bytes())?;
+            w.write_all(editor_id.as_bytes())?;
+            w.write_all(&form_id.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Read an index written by `write_cache`, with the key it was written with.
+    pub fn read_cache<R: Read>(r: &mut R) -> Result<(CacheKey, FormIdIndex)> {
+        fn bytes<const N: usize>(r: &mut impl Read) -> Result<[u8; N]> {
+            let mut bytes = [0; N];
+            r.read_exact(&mut bytes)?;
+            Ok(bytes)
+        }
+        let read_u32 = |r: &mut R| bytes::<4>(r).map(u32::from_le_bytes);
+        let read_u64 = |r: &mut R| bytes::<8>(r).map(u64::from_le_bytes);
+
+        let magic = bytes::<8>(r)?;
+        if &magic != MAGIC { return Err(Error::unexpected(0, "index cache", format!("{:?}", magic))) }
+        let version = read_u32(r)?;
+        if version != VERSION { return Err(Error::unexpected(8, format!("cache version {}", VERSION), version.to_string())) }
+
+        let key = CacheKey { size: read_u64(r)?, modified: u128::from_le_bytes(bytes(r)?), hash: read_u64(r)? };
+        let code = bytes::<1>(r)?[0];
+        let game = game_from_code(code).ok_or_else(|| Error::unexpected(0, "game", code.to_string()))?;
+        let mut index = FormIdIndex { game, ..Default::default() };
+
+        for _ in 0..read_u32(r)? {
+            // lengths come from the file, so nothing is reserved up front
+            let mut path = Vec::new();
+            for _ in 0..read_u32(r)? {
+                path.push(GroupKey { group_type: read_u32(r)?, label: bytes(r)? });
+            }
+            index.paths.push(path);
+        }
+
+        for _ in 0..read_u32(r)? {
+            let form_id = read_u32(r)?;
+            let entry = IndexEntry {
+                type_id: FourCC(bytes(r)?),
+                offset: read_u64(r)?,
+                size: read_u32(r)?,
+                compressed: bytes::<1>(r)?[0] != 0,
+                group: read_u32(r)?
+            };
+            index.entries.insert(form_id, entry);
+        }
+
+        for _ in 0..read_u32(r)? {
+            let length = read_u32(r)?;
+            if length > MAX_EDITOR_ID { return Err(Error::unexpected(0, "EditorID length", length.to_string())) }
+            let mut editor_id = Vec::new();
+            r.by_ref().take(length as u64).read_to_end(&mut editor_id)?;
+            if editor_id.len() != length as usize { return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into()) }
+            index.editor_ids.insert(decode_string(&editor_id), read_u32(r)?);
+        }
+
+        Ok((key, index))
+    }
+}
+
+//------------------------------------------------------------------------------
+
+impl ESMParser2<BufReader<File>> {
+    /// Open a plugin like `ESMParser2::file`, along with its index loaded from
+    /// the sidecar cache, or scanned and cached if the cache is out of date.
+    ///
+    /// The TES4 header has been parsed, so records can be loaded straight away
+    /// with `load_record` or `load_form`.
+    pub fn file_indexed(path: impl AsRef<Path>) -> Result<(Self, FormIdIndex)> {
+        let index = FormIdIndex::load_or_scan(path.as_ref())?;
+        let mut parser = ESMParser2::new(BufReader::new(File::open(path)?));
+        parser.parse_file_header()?;
+        Ok((parser, index))
+    }
+}
@@ -1,14 +1,20 @@
 
 use crate::*;
 
+#[derive(Debug)]
+pub enum GroupChild<'a> {
+    Record(Record<'a>),
+    Group(Group<'a>)
+}
 
-pub enum TopGroup {
+pub enum TopGroup<'a> {
     Skipped,
-    Unhandled(Vec<Record>),
-    GameSetting(Vec<Record>),
-    Keyword(Vec<Record>),
-    Worldspace(Vec<WorldEntry>),
-    Cell(Vec<Group>),
+    Unhandled(Vec<Record<'a>>),
+    GameSetting(Vec<GameSetting<'a>>),
+    Keyword(Vec<Keyword<'a>>),
+    Worldspace(Vec<WorldEntry<'a>>),
+    Cell(Vec<InteriorCellBlock<'a>>),
+    Dialogue(Vec<Dialog<'a>>),
     Reference,
     Landscape,
     Action,
@@ -17,7 +23,7 @@ pub enum TopGroup {
     Ingredient,
     Race,
     Zoom,
-    Quest,
+    Quest(Vec<Quest<'a>>),
     Relationship,
     MovementType,
     Ammo,
@@ -133,4 +139,66 @@ pub enum TopGroup {
     Static,
     AttractionRule,
     StoryManagerBranchNode
-}
\ No newline at end of file
+}
+
+impl<'a> TopGroup<'a> {
+    /// Every record in the group, including those in nested groups, in file order.
+    pub fn records(&self) -> Vec<&Record<'a>> {
+        let mut out = Vec::new();
+        match self {
+            TopGroup::Unhandled(records) => out.extend(records),
+            TopGroup::GameSetting(settings) => out.extend(settings.iter().map(|setting| &setting.setting)),
+            TopGroup::Keyword(keywords) => out.extend(keywords.iter().map(|keyword| &keyword.keyword)),
+            TopGroup::Worldspace(worlds) => for entry in worlds {
+                out.push(&entry.world);
+                if let Some(children) = &entry.world_children {
+                    out.extend(&children.road);
+                    if let Some(cell) = &children.cell { cell_records(cell, &mut out); }
+                    for block in &children.blocks {
+                        for sub_block in &block.sub_blocks {
+                            for cell in &sub_block.cells { cell_records(cell, &mut out); }
+                        }
+                    }
+                }
+            },
+            TopGroup::Cell(blocks) => for block in blocks {
+                for sub_block in &block.sub_blocks {
+                    for cell in &sub_block.cells { cell_records(cell, &mut out); }
+                }
+            },
+            TopGroup::Dialogue(dialogs) => for dialog in dialogs {
+                out.push(&dialog.dialog);
+                if let Some(children) = &dialog.children { group_records(children, &mut out); }
+            },
+            TopGroup::Quest(quests) => for quest in quests {
+                out.push(&quest.quest);
+                if let Some(children) = &quest.children { group_records(children, &mut out); }
+            },
+            _ => {}
+        }
+        out
+    }
+}
+
+fn group_records<'g, 'a>(group: &'g Group<'a>, out: &mut Vec<&'g Record<'a>>) {
+    for child in &group.children {
+        match child {
+            GroupChild::Record(record) => out.push(record),
+            GroupChild::Group(group) => group_records(group, out)
+        }
+    }
+}
+
+/// Records of a cell and its children, in file order of the child groups.
+fn cell_records<'g, 'a>(cell: &'g Cell<'a>, out: &mut Vec<&'g Record<'a>>) {
+    out.push(&cell.cell);
+    let Some(children) = &cell.cell_children else { return };
+    for subgroup in &children.subgroups {
+        let records = match subgroup.get_label() {
+            GroupLabel::CellPersistentChildren(_) => &children.persistant,
+            GroupLabel::CellTemporaryChildren(_) => &children.temporary,
+            _ => &children.visible_distant
+        };
+        if let Some(records) = records { out.extend(records); }
+    }
+}
@@ -0,0 +1,76 @@
+//! ESM parser error type.
+
+use std::fmt;
+
+//------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub enum Error {
+    /// Error from the underlying chunk parser or reader.
+    Chunk(chunk_parser::Error),
+    /// Input did not have the expected structure.
+    Unexpected {
+        /// Byte offset at which the problem was detected.
+        offset: u64,
+        /// Group and record path leading to the problem.
+        path: String,
+        expected: String,
+        found: String
+    },
+    /// Valid input that the parser does not support yet.
+    Unimplemented {
+        offset: u64,
+        path: String,
+        what: &'static str
+    },
+    /// A builder was asked for something the plugin cannot hold.
+    Invalid(String)
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl Error {
+    /// Unexpected input at an offset relative to the data being decoded.
+    pub fn unexpected(offset: u64, expected: impl Into<String>, found: impl Into<String>) -> Error {
+        Error::Unexpected { offset, path: String::new(), expected: expected.into(), found: found.into() }
+    }
+
+    /// Invalid use of a builder, such as a child for a parent that was never added.
+    pub fn invalid(what: impl Into<String>) -> Error {
+        Error::Invalid(what.into())
+    }
+
+    /// Make a relative offset absolute and attach the path, if not already set.
+    pub fn located(self, base: u64, at: &str) -> Error {
+        match self {
+            Error::Unexpected { offset, path, expected, found } if path.is_empty() =>
+                Error::Unexpected { offset: base + offset, path: at.to_owned(), expected, found },
+            Error::Unimplemented { offset, path, what } if path.is_empty() =>
+                Error::Unimplemented { offset: base + offset, path: at.to_owned(), what },
+            e => e
+        }
+    }
+}
+
+impl From<chunk_parser::Error> for Error {
+    fn from(e: chunk_parser::Error) -> Self { Error::Chunk(e) }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self { Error::Chunk(e.into()) }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Chunk(e) => write!(f, "{:?}", e),
+            Error::Unexpected { offset, path, expected, found } =>
+                write!(f, "at {:#x} ({}): expected {}, found {}", offset, path, expected, found),
+            Error::Unimplemented { offset, path, what } =>
+                write!(f, "at {:#x} ({}): {} is not implemented", offset, path, what),
+            Error::Invalid(what) => write!(f, "invalid: {}", what)
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,132 @@
+//! Game and format version detection.
+
+//------------------------------------------------------------------------------
+
+/// Game a plugin was made for.
+///
+/// The game decides the record and group header size, the TES4 flags (such as
+/// whether the localised flag applies) and the record types accepted in cell
+/// children. Records are otherwise kept as raw fields with the
+/// `esm_bindings::fo3` header types for every game, and only the decoders in
+/// this crate (`FileHeader`, `GameSettingValue`, `QuestData`, ...) interpret
+/// field contents. Per-game record definitions are not selected: the bindings
+/// only provide the Fallout 3 layout, so decoding record contents per game is
+/// left to callers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Game {
+    Oblivion,
+    #[default]
+    Fallout3,
+    FalloutNV,
+    SkyrimLE,
+    SkyrimSE,
+    Fallout4,
+    Starfield
+}
+
+impl Game {
+    /// Number of bytes read to detect the game, enough for the TES4 header and HEDR field.
+    pub const DETECT_SIZE: usize = 42;
+
+    /// Detect the game from the start of a plugin.
+    ///
+    /// Uses the record header layout (Oblivion has no version control fields, so
+    /// HEDR follows a 20 byte header), the HEDR version and, where HEDR versions
+    /// are shared between games, the form version of the TES4 record.
+    pub fn detect(bytes: &[u8]) -> Option<Game> {
+        if bytes.get(0..4)? != b"TES4" { return None }
+
+        if bytes.get(20..24)? == b"HEDR" {
+            return Some(Game::Oblivion)
+        }
+        if bytes.get(24..28)? != b"HEDR" { return None }
+
+        let version = bytes.get(30..34).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))?;
+        let form_version = bytes.get(20..22).map(|b| u16::from_le_bytes([b[0], b[1]]))?;
+        let is = |v: f32| (version - v).abs() < 0.001;
+
+        let game = if is(0.96) { Game::Starfield }
+        else if is(0.95) { Game::Fallout4 }
+        else if is(1.7) || is(1.71) { Game::SkyrimSE }
+        else if (1.3..1.4).contains(&version) { Game::FalloutNV }
+        else if is(0.94) && form_version <= 15 { Game::Fallout3 }
+        else if is(0.94) { Game::SkyrimLE }
+        // fall back on the form version for files written by other tools
+        else {
+            match form_version {
+                0..=15 => Game::Fallout3,
+                16..=43 => Game::SkyrimLE,
+                44..=130 => Game::SkyrimSE,
+                131..=499 => Game::Fallout4,
+                _ => Game::Starfield
+            }
+        };
+        Some(game)
+    }
+
+    /// HEDR version written by the game's own tools.
+    pub fn hedr_version(&self) -> f32 {
+        match self {
+            Game::Oblivion => 1.0,
+            Game::Fallout3 | Game::SkyrimLE => 0.94,
+            Game::FalloutNV => 1.34,
+            Game::SkyrimSE => 1.71,
+            Game::Fallout4 => 0.95,
+            Game::Starfield => 0.96
+        }
+    }
+
+    /// Form version written in record headers, zero for Oblivion which has none.
+    pub fn form_version(&self) -> u16 {
+        match self {
+            Game::Oblivion => 0,
+            Game::Fallout3 | Game::FalloutNV => 15,
+            Game::SkyrimLE => 43,
+            Game::SkyrimSE => 44,
+            Game::Fallout4 => 131,
+            Game::Starfield => 555
+        }
+    }
+
+    /// Size of record and group headers.
+    pub fn header_size(&self) -> u64 {
+        match self {
+            Game::Oblivion => 20,
+            _ => 24
+        }
+    }
+
+    /// Whether the TES4 localised flag is supported, with strings in external tables.
+    pub fn is_localisable(&self) -> bool {
+        matches!(self, Game::SkyrimLE | Game::SkyrimSE | Game::Fallout4 | Game::Starfield)
+    }
+
+    /// Whether light (ESL) masters are supported.
+    pub fn has_light_masters(&self) -> bool {
+        matches!(self, Game::SkyrimSE | Game::Fallout4 | Game::Starfield)
+    }
+
+    /// Record types that may appear in cell persistent, temporary and visible distant children.
+    pub fn cell_child_types(&self) -> &'static [&'static [u8; 4]] {
+        match self {
+            Game::Oblivion => &[b"REFR", b"ACHR", b"ACRE", b"PGRD", b"LAND"],
+            // PHZD is not written by these games, but was accepted before games were detected
+            Game::Fallout3 | Game::FalloutNV => &[b"REFR", b"ACHR", b"ACRE", b"PGRE", b"PHZD", b"PMIS", b"PBEA", b"PFLA", b"PCBE", b"NAVM", b"LAND"],
+            _ => &[b"REFR", b"ACHR", b"PGRE", b"PHZD", b"PMIS", b"PARW", b"PBAR", b"PBEA", b"PCON", b"PFLA", b"NAVM", b"LAND"]
+        }
+    }
+}
+
+impl std::fmt::Display for Game {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Game::Oblivion => "Oblivion",
+            Game::Fallout3 => "Fallout 3",
+            Game::FalloutNV => "Fallout: New Vegas",
+            Game::SkyrimLE => "Skyrim",
+            Game::SkyrimSE => "Skyrim Special Edition",
+            Game::Fallout4 => "Fallout 4",
+            Game::Starfield => "Starfield"
+        })
+    }
+}
@@ -0,0 +1,38 @@
+//! Game setting (GMST) value decoding.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+/// Value of a game setting, typed by the first letter of its EditorID.
+#[derive(Debug, Clone, PartialEq)]
+pub enum GameSettingValue {
+    /// `f` prefix
+    Float(f32),
+    /// `i` prefix
+    Int(i32),
+    /// `s` prefix
+    String(LString),
+    /// `b` prefix
+    Bool(bool),
+    /// `u` prefix
+    UInt(u32),
+    /// Unknown prefix or missing DATA, kept as raw data.
+    Unknown(Vec<u8>)
+}
+
+impl GameSettingValue {
+    /// Decode the DATA field of a GMST record by the prefix of its EditorID.
+    pub fn decode(editor_id: &str, data: &Field, localised: bool) -> GameSettingValue {
+        let value = match editor_id.as_bytes().first() {
+            Some(b'f') => data.f32_at(0).map(GameSettingValue::Float),
+            Some(b'i') => data.u32_at(0).map(|v| GameSettingValue::Int(v as i32)),
+            Some(b's') => Some(GameSettingValue::String(data.lstring(localised))),
+            // a four byte integer in most games, a single byte in some
+            Some(b'b') => data.data.first().map(|_| GameSettingValue::Bool(data.data.iter().any(|&b| b != 0))),
+            Some(b'u') => data.u32_at(0).map(GameSettingValue::UInt),
+            _ => None
+        };
+        value.unwrap_or_else(|| GameSettingValue::Unknown(data.data.to_vec()))
+    }
+}
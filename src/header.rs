@@ -0,0 +1,166 @@
+//! TES4 file header.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+/// TES4 record flags.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileFlags(pub u32);
+
+impl FileFlags {
+    /// Master (ESM) file.
+    pub fn is_master(&self) -> bool { self.0 & 0x00000001 != 0 }
+
+    /// Strings are stored in external string tables.
+    pub fn is_localised(&self) -> bool { self.0 & 0x00000080 != 0 }
+
+    /// Strings are stored in external string tables, in games that support them.
+    pub fn is_localised_for(&self, game: Game) -> bool {
+        game.is_localisable() && self.is_localised()
+    }
+
+    /// Light Master (ESL) file, in games before Starfield.
+    pub fn is_light(&self) -> bool { self.0 & 0x00000200 != 0 }
+
+    /// Medium Master file (Starfield).
+    pub fn is_medium(&self) -> bool { self.0 & 0x00000400 != 0 }
+
+    /// Light Master file, whose flag moved to 0x100 in Starfield.
+    pub fn is_light_for(&self, game: Game) -> bool {
+        match game {
+            Game::Starfield => self.0 & 0x00000100 != 0,
+            _ => game.has_light_masters() && self.is_light()
+        }
+    }
+
+    /// Overlay (update) file whose records may be partial forms (Starfield).
+    pub fn is_overlay_for(&self, game: Game) -> bool {
+        game == Game::Starfield && self.0 & 0x00000200 != 0
+    }
+
+    /// FormID range used by the file's own records.
+    pub fn master_kind(&self, game: Game) -> MasterKind {
+        if self.is_light_for(game) { MasterKind::Light }
+        else if game == Game::Starfield && self.is_medium() { MasterKind::Medium }
+        else { MasterKind::Full }
+    }
+}
+
+/// Load order slot a plugin occupies, which decides the range of its FormIDs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MasterKind {
+    /// One of 253 full slots, with 24 bit object IDs.
+    #[default]
+    Full,
+    /// One of 256 slots under index 0xFD, with 16 bit object IDs (Starfield).
+    Medium,
+    /// One of 4096 slots under index 0xFE, with 12 bit object IDs.
+    Light
+}
+
+impl MasterKind {
+    /// Largest object ID a plugin of this kind may use.
+    pub fn max_object_id(&self) -> u32 {
+        match self {
+            MasterKind::Full => 0x00FFFFFF,
+            MasterKind::Medium => 0x0000FFFF,
+            MasterKind::Light => 0x00000FFF
+        }
+    }
+
+    /// Object ID part of a FormID.
+    pub fn object_id(&self, form_id: u32) -> u32 {
+        form_id & self.max_object_id()
+    }
+
+    /// Runtime FormID of an object in the plugin loaded at `index` within its kind's slots.
+    pub fn load_order_form_id(&self, index: u16, object_id: u32) -> u32 {
+        let object_id = self.object_id(object_id);
+        match self {
+            MasterKind::Full => (index as u32 & 0xFF) << 24 | object_id,
+            MasterKind::Medium => 0xFD000000 | (index as u32 & 0xFF) << 16 | object_id,
+            MasterKind::Light => 0xFE000000 | (index as u32 & 0xFFF) << 12 | object_id
+        }
+    }
+}
+
+/// Master file entry, a MAST/DATA pair.
+#[derive(Debug, Clone)]
+pub struct Master {
+    pub name: String,
+    pub size: u64
+}
+
+/// Decoded TES4 file header.
+#[derive(Debug, Default, Clone)]
+pub struct FileHeader {
+    /// Game the flags are interpreted for.
+    pub game: Game,
+    /// HEDR version.
+    pub version: f32,
+    /// HEDR number of records and groups.
+    pub num_records: u32,
+    /// HEDR next available object ID.
+    pub next_object_id: u32,
+    /// CNAM
+    pub author: Option<String>,
+    /// SNAM
+    pub description: Option<String>,
+    /// Masters in load order.
+    pub masters: Vec<Master>,
+    /// ONAM overridden forms.
+    pub overrides: Vec<u32>,
+    pub flags: FileFlags
+}
+
+impl FileHeader {
+    /// Decode a parsed TES4 record of a plugin for `game`.
+    pub fn from_record(record: &Record, game: Game) -> Result<FileHeader> {
+        if record.header.type_id != b"TES4" {
+            return Err(Error::unexpected(0, "TES4 record", record.header.type_id.to_string()))
+        }
+        let truncated = |field: &Field| Error::unexpected(0, format!("longer {} field", field.type_id), format!("{} bytes", field.data.len()));
+
+        let mut header = FileHeader { game, flags: FileFlags(record.header.flags), ..Default::default() };
+        let mut has_hedr = false;
+
+        for field in &record.fields {
+            match &field.type_id.0 {
+                b"HEDR" => {
+                    header.version = field.f32_at(0).ok_or_else(|| truncated(field))?;
+                    header.num_records = field.u32_at(4).ok_or_else(|| truncated(field))?;
+                    header.next_object_id = field.u32_at(8).ok_or_else(|| truncated(field))?;
+                    has_hedr = true;
+                },
+                b"CNAM" => { header.author = Some(field.zstring()); },
+                b"SNAM" => { header.description = Some(field.zstring()); },
+                b"MAST" => { header.masters.push(Master { name: field.zstring(), size: 0 }); },
+                b"DATA" => {
+                    // size of the master file that precedes it
+                    let size = field.u64_at(0).ok_or_else(|| truncated(field))?;
+                    if let Some(master) = header.masters.last_mut() { master.size = size; }
+                },
+                b"ONAM" => {
+                    header.overrides = field.data.chunks_exact(4)
+                        .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+                        .collect();
+                },
+                _ => {}
+            }
+        }
+
+        if !has_hedr { return Err(Error::unexpected(0, "HEDR field", "none")) }
+        Ok(header)
+    }
+
+    /// FormID range used by the plugin's own records.
+    pub fn master_kind(&self) -> MasterKind {
+        self.flags.master_kind(self.game)
+    }
+
+    /// Master file names in load order.
+    pub fn master_names(&self) -> impl Iterator<Item = &str> {
+        self.masters.iter().map(|master| master.name.as_str())
+    }
+}
@@ -0,0 +1,168 @@
+//! Header-only scan building a FormID index, for loading records on demand.
+
+use crate::*;
+
+use std::collections::HashMap;
+
+//------------------------------------------------------------------------------
+
+/// Type and label of a group, one step of a group path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GroupKey {
+    pub group_type: u32,
+    pub label: [u8; 4]
+}
+
+/// Location of a record found by `ESMParser2::scan`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    pub type_id: FourCC,
+    /// Stream position of the record header.
+    pub offset: u64,
+    /// Size of the record data, as stored in the header.
+    pub size: u32,
+    pub compressed: bool,
+    /// Index of the parent group path, see `FormIdIndex::group_path`.
+    pub group: u32
+}
+
+/// Records of a plugin by form ID.
+#[derive(Debug, Default, Clone)]
+pub struct FormIdIndex {
+    pub game: Game,
+    entries: HashMap<u32, IndexEntry>,
+    /// Group paths from the top group down, shared by the records they contain.
+    paths: Vec<Vec<GroupKey>>,
+    /// Form IDs by EditorID, filled by `ESMParser2::scan_editor_ids`.
+    editor_ids: HashMap<String, u32>
+}
+
+impl FormIdIndex {
+    /// Location of a record. A form ID stored more than once is indexed at its first record.
+    pub fn get(&self, form_id: u32) -> Option<&IndexEntry> {
+        self.entries.get(&form_id)
+    }
+
+    /// Groups containing a record, from its top group down.
+    pub fn group_path(&self, entry: &IndexEntry) -> &[GroupKey] {
+        self.paths.get(entry.group as usize).map_or(&[], Vec::as_slice)
+    }
+
+    /// Form ID of the record with an EditorID.
+    pub fn form_id(&self, editor_id: &str) -> Option<u32> {
+        self.editor_ids.get(editor_id).copied()
+    }
+
+    /// Indexed form IDs and their locations, in no particular order.
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &IndexEntry)> {
+        self.entries.iter().map(|(form_id, entry)| (*form_id, entry))
+    }
+
+    pub fn len(&self) -> usize { self.entries.len() }
+
+    pub fn is_empty(&self) -> bool { self.entries.is_empty() }
+}
+
+//------------------------------------------------------------------------------
+
+impl<'a, R> ESMParser2<R> where R: Source<'a> {
+    /// Index every record by reading only record and group headers and
+    /// seeking past record data.
+    pub fn scan(&mut self) -> Result<FormIdIndex> {
+        self.scan_with(false)
+    }
+
+    /// Index every record like `scan`, also reading EditorIDs.
+    ///
+    /// Only the first field of each record is read, but compressed records
+    /// have to be inflated.
+    pub fn scan_editor_ids(&mut self) -> Result<FormIdIndex> {
+        self.scan_with(true)
+    }
+
+    fn scan_with(&mut self, editor_ids: bool) -> Result<FormIdIndex> {
+        let total_size = self.reader().seek(std::io::SeekFrom::End(0))?;
+        self.detect_game()?;
+
+        // skip the TES4 record
+        let header = self.read_record_header()?;
+        self.skip(header.size as u64)?;
+
+        let mut index = FormIdIndex { game: self.game, paths: vec![Vec::new()], ..Default::default() };
+        // end position and path index of each open group
+        let mut groups: Vec<(u64, u32)> = Vec::new();
+
+        // headers are read once and record data is skipped with relative seeks,
+        // so a buffered reader keeps its buffer across small records
+        let header_size = self.game.header_size();
+        let mut offset = self.reader().stream_position()?;
+        while offset < total_size {
+            while groups.last().is_some_and(|&(end, _)| end <= offset) { groups.pop(); }
+            let parent = groups.last().map_or(0, |&(_, path)| path);
+
+            let header = self.read_record_header()?;
+            if header.type_id == b"GRUP" {
+                // group and record headers have the same size
+                let header: GroupHeader = unsafe { std::mem::transmute(header) };
+                if (header.size as u64) < header_size {
+                    return Err(self.unexpected("group larger than its header", header.size.to_string()))
+                }
+                let mut path = index.paths[parent as usize].clone();
+                path.push(GroupKey { group_type: group_type(&header), label: group_label(&header) });
+                index.paths.push(path);
+                groups.push((offset + header.size as u64, index.paths.len() as u32 - 1));
+                offset += header_size;
+            } else {
+                let form_id = form_id(&header);
+                let compressed = header.flags & RECORD_FLAG_COMPRESSED != 0;
+                index.entries.entry(form_id).or_insert(IndexEntry {
+                    type_id: header.type_id, offset, size: header.size, compressed, group: parent
+                });
+                let end = offset + header_size + header.size as u64;
+                let mut pos = offset + header_size;
+                if editor_ids {
+                    if let Some(editor_id) = self.read_editor_id(&header)? {
+                        index.editor_ids.entry(editor_id).or_insert(form_id);
+                    }
+                    pos = self.reader().stream_position()?;
+                }
+                self.reader().seek_relative(end as i64 - pos as i64)?;
+                offset = end;
+            }
+        }
+
+        Ok(index)
+    }
+
+    /// EditorID of a record whose header has just been read, if it is the first field.
+    fn read_editor_id(&mut self, header: &RecordHeader) -> Result<Option<String>> {
+        if header.flags & RECORD_FLAG_COMPRESSED != 0 {
+            let data = self.deflate(header.size)?;
+            let fields = parse_field_data(&data)?;
+            return Ok(fields.first().filter(|field| field.type_id == b"EDID").map(Field::zstring))
+        }
+        if header.size < 6 { return Ok(None) }
+        let field: [u8; 6] = self.read()?;
+        let size = u16::from_le_bytes([field[4], field[5]]) as u32;
+        if &field[..4] != b"EDID" || size > header.size - 6 { return Ok(None) }
+        let mut data = vec![0; size as usize];
+        self.reader().read_exact(&mut data)?;
+        Ok(Some(Field { type_id: FourCC(*b"EDID"), data: data.into() }.zstring()))
+    }
+
+    /// Load an indexed record, read with the header size of the parser's
+    /// game as set by `parse_file_header`, `scan` or `load_form`.
+    pub fn load_record(&mut self, entry: &IndexEntry) -> Result<Record<'a>> {
+        self.reader().seek(std::io::SeekFrom::Start(entry.offset))?;
+        self.parse_record()
+    }
+
+    /// Load a record by form ID, or `None` if the index has no such record.
+    pub fn load_form(&mut self, index: &FormIdIndex, form_id: u32) -> Result<Option<Record<'a>>> {
+        self.game = index.game;
+        match index.get(form_id) {
+            Some(entry) => Ok(Some(self.load_record(entry)?)),
+            None => Ok(None)
+        }
+    }
+}
@@ -0,0 +1,81 @@
+//! Keyword arrays (KSIZ/KWDA) and keyword lookups.
+
+use crate::*;
+
+use std::collections::HashMap;
+
+//------------------------------------------------------------------------------
+
+impl<'a> Record<'a> {
+    /// Form IDs of the KWDA keyword array, limited to the KSIZ count when present.
+    pub fn keywords(&self) -> Vec<u32> {
+        let Some(kwda) = self.field(b"KWDA") else { return Vec::new() };
+        let count = self.field(b"KSIZ")
+            .and_then(|ksiz| ksiz.u32_at(0))
+            .map_or(usize::MAX, |count| count as usize);
+        kwda.data.chunks_exact(4)
+            .take(count)
+            .map(|id| u32::from_le_bytes([id[0], id[1], id[2], id[3]]))
+            .collect()
+    }
+
+    /// Whether the KWDA keyword array contains a keyword.
+    pub fn has_keyword(&self, keyword: u32) -> bool {
+        self.keywords().contains(&keyword)
+    }
+}
+
+//------------------------------------------------------------------------------
+
+/// Records by the keywords they carry, and keywords by EditorID.
+#[derive(Debug, Default)]
+pub struct KeywordIndex {
+    records: HashMap<u32, Vec<u32>>,
+    editor_ids: HashMap<String, u32>
+}
+
+impl KeywordIndex {
+    /// Index every record of the given top groups, including records inside
+    /// cells, worldspaces and other nested groups.
+    pub fn from_top_groups<'g>(groups: impl IntoIterator<Item = &'g TopGroup<'g>>) -> KeywordIndex {
+        let mut index = KeywordIndex::default();
+        for group in groups {
+            if let TopGroup::Keyword(keywords) = group {
+                for keyword in keywords { index.add_keyword(keyword); }
+            }
+            for record in group.records() { index.add(record); }
+        }
+        index
+    }
+
+    /// Add the keywords of a record.
+    pub fn add(&mut self, record: &Record) {
+        let form_id = record.form_id();
+        for keyword in record.keywords() {
+            let records = self.records.entry(keyword).or_default();
+            if records.last() != Some(&form_id) { records.push(form_id); }
+        }
+    }
+
+    /// Add a KYWD record so it can be found by EditorID.
+    pub fn add_keyword(&mut self, keyword: &Keyword) {
+        if let Some(editor_id) = &keyword.editor_id {
+            self.editor_ids.insert(editor_id.clone(), keyword.keyword.form_id());
+        }
+    }
+
+    /// Form ID of a keyword added with `add_keyword`.
+    pub fn keyword(&self, editor_id: &str) -> Option<u32> {
+        self.editor_ids.get(editor_id).copied()
+    }
+
+    /// Form IDs of all records carrying a keyword, in the order they were added.
+    pub fn records_with(&self, keyword: u32) -> &[u32] {
+        self.records.get(&keyword).map_or(&[], Vec::as_slice)
+    }
+
+    /// Form IDs of all records carrying the keyword with the given EditorID.
+    pub fn records_with_editor_id(&self, editor_id: &str) -> &[u32] {
+        self.keyword(editor_id).map_or(&[], |keyword| self.records_with(keyword))
+    }
+}
@@ -3,17 +3,64 @@
 #![allow(non_snake_case)]
 
 use chunk_parser::prelude::*;
-pub use chunk_parser::Result;
 use esm_bindings::fo3::*;
 
+use std::borrow::Cow;
 use std::ffi::CString;
-use std::io::Read;
+use std::io::{Read, Write};
 
 use flate2::read::ZlibDecoder;
 
 mod enums;
 pub use enums::*;
 
+mod error;
+pub use error::{Error, Result};
+
+mod header;
+pub use header::*;
+
+mod game;
+pub use game::*;
+
+mod strings;
+pub use strings::*;
+
+mod quest;
+pub use quest::*;
+
+mod tes3;
+pub use tes3::*;
+
+mod gmst;
+pub use gmst::*;
+
+mod keyword;
+pub use keyword::*;
+
+mod index;
+pub use index::*;
+
+mod cache;
+pub use cache::*;
+
+mod source;
+pub use source::*;
+
+mod parallel;
+
+mod visitor;
+pub use visitor::*;
+
+mod printer;
+pub use printer::*;
+
+mod writer;
+pub use writer::*;
+
+mod builder;
+pub use builder::*;
+
 
 //------------------------------------------------------------------------------
 
@@ -25,69 +72,300 @@ pub struct ESMParser {
 
 #[chunk_parser(custom,depth)]
 pub struct ESMParser2 {
-    localised: bool
+    game: Game,
+    localised: bool,
+    strings: Option<std::sync::Arc<StringTables>>,
+    path: Vec<String>,
+    /// Keep the raw data of compressed records, see `keep_compressed`.
+    keep_compressed: bool,
+    /// Records inflated ahead of parsing, by the offset of their data.
+    inflated: Option<std::collections::HashMap<u64, Vec<u8>>>
 }
 
 //------------------------------------------------------------------------------
 
 type RecordParser<P> = fn(parser: &mut P, header: &RecordHeader) -> Result<()>;
-type FieldParser<P> = fn(parser: &mut P, header: &FieldHeader) -> Result<()>;
+type FieldParser<P> = fn(parser: &mut P, field: &Field) -> Result<()>;
+
+//------------------------------------------------------------------------------
+
+/// Record flag set when the record data is zlib compressed.
+pub const RECORD_FLAG_COMPRESSED: u32 = 0x00040000;
+
+/// Record flag set on partial forms in Starfield overlay plugins, which only
+/// override the fields they contain.
+pub const RECORD_FLAG_PARTIAL: u32 = 0x00004000;
+
+/// Inflate the data of a compressed record, the uncompressed size followed by zlib data.
+pub fn inflate_record(data: &[u8]) -> Result<Vec<u8>> {
+    if data.len() < 4 { return Err(Error::unexpected(0, "compressed record data", format!("{} bytes", data.len()))) }
+    inflate(&data[4..], u32::from_le_bytes([data[0], data[1], data[2], data[3]]))
+}
+
+/// Upper bound on the buffer reserved up front for inflated data, the declared
+/// size comes from the file and is not trusted beyond this.
+const INFLATE_PREALLOCATE: usize = 1 << 24;
+
+/// Inflate a zlib compressed record body, checking the declared uncompressed size.
+///
+/// At most one byte more than the declared size is inflated, so a corrupt or
+/// malicious stream cannot expand without bound.
+pub fn inflate(compressed: &[u8], uncompressed_size: u32) -> Result<Vec<u8>> {
+    let mut data = Vec::with_capacity((uncompressed_size as usize).min(INFLATE_PREALLOCATE));
+    ZlibDecoder::new(compressed).take(uncompressed_size as u64 + 1).read_to_end(&mut data)?;
+    if data.len() != uncompressed_size as usize {
+        return Err(Error::unexpected(0, format!("{} uncompressed bytes", uncompressed_size), format!("{} bytes", data.len())))
+    }
+    Ok(data)
+}
 
 //------------------------------------------------------------------------------
 
+/// Form ID of a record header, read from its raw bytes so it does not depend
+/// on the header layout of a particular game.
+pub fn form_id(header: &RecordHeader) -> u32 {
+    let bytes: [u8; std::mem::size_of::<RecordHeader>()] = unsafe { std::mem::transmute(*header) };
+    u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]])
+}
+
+/// Raw label of a group header.
+pub fn group_label(header: &GroupHeader) -> [u8; 4] {
+    let bytes: [u8; std::mem::size_of::<GroupHeader>()] = unsafe { std::mem::transmute(*header) };
+    [bytes[8], bytes[9], bytes[10], bytes[11]]
+}
+
+/// Raw group type of a group header.
+pub fn group_type(header: &GroupHeader) -> u32 {
+    let bytes: [u8; std::mem::size_of::<GroupHeader>()] = unsafe { std::mem::transmute(*header) };
+    u32::from_le_bytes([bytes[12], bytes[13], bytes[14], bytes[15]])
+}
+
+/// Grid coordinates (x, y) of an exterior cell block or sub-block, stored as Y then X.
+pub fn group_grid(header: &GroupHeader) -> (i16, i16) {
+    let label = group_label(header);
+    (i16::from_le_bytes([label[2], label[3]]), i16::from_le_bytes([label[0], label[1]]))
+}
+
 #[derive(Debug)]
-pub struct Record {
+pub struct Record<'a> {
     pub header: RecordHeader,
-    pub fields: Vec<Field>
+    pub fields: Vec<Field<'a>>,
+    /// Data of a compressed record as read, kept when the parser was asked to
+    /// with `keep_compressed` so an unmodified record is written back unchanged
+    /// rather than recompressed.
+    pub compressed: Option<Cow<'a, [u8]>>
 }
 
-#[derive(Debug)]
-pub struct Group {
-    pub header: GroupHeader
+impl<'a> Record<'a> {
+    /// Form ID of the record.
+    pub fn form_id(&self) -> u32 {
+        form_id(&self.header)
+    }
+
+    /// Whether this is a partial form, see `RECORD_FLAG_PARTIAL`.
+    pub fn is_partial(&self) -> bool {
+        self.header.flags & RECORD_FLAG_PARTIAL != 0
+    }
+
+    /// First field of the given type.
+    pub fn field(&self, type_id: &[u8;4]) -> Option<&Field<'a>> {
+        self.fields.iter().find(|field| field.type_id == type_id)
+    }
+
+    /// Copy any borrowed field data so the record owns it.
+    pub fn into_owned(self) -> Record<'static> {
+        Record {
+            header: self.header,
+            fields: self.fields.into_iter().map(Field::into_owned).collect(),
+            compressed: self.compressed.map(|data| Cow::Owned(data.into_owned()))
+        }
+    }
 }
 
 #[derive(Debug)]
-pub struct Field {
-    pub header: FieldHeader
+pub struct Group<'a> {
+    pub header: GroupHeader,
+    pub children: Vec<GroupChild<'a>>
+}
+
+impl<'a> Group<'a> {
+    /// Records directly inside the group, in file order.
+    pub fn records(&self) -> impl Iterator<Item = &Record<'a>> {
+        self.children.iter().filter_map(|child| match child {
+            GroupChild::Record(record) => Some(record),
+            GroupChild::Group(_) => None
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Field<'a> {
+    pub type_id: FourCC,
+    pub data: Cow<'a, [u8]>
+}
+
+impl<'a> Field<'a> {
+    /// Copy borrowed data so the field owns it.
+    pub fn into_owned(self) -> Field<'static> {
+        Field { type_id: self.type_id, data: Cow::Owned(self.data.into_owned()) }
+    }
+
+    /// Size of the field when written, including its header and any `XXXX` prefix.
+    pub fn encoded_len(&self) -> usize {
+        let len = self.data.len();
+        if len > u16::MAX as usize { 6 + 4 + 6 + len } else { 6 + len }
+    }
+
+    /// Write the field, preceded by an `XXXX` field when the data exceeds 65535 bytes.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        let len = self.data.len();
+        if len > u16::MAX as usize {
+            w.write_all(b"XXXX")?;
+            w.write_all(&4u16.to_le_bytes())?;
+            w.write_all(&(len as u32).to_le_bytes())?;
+            w.write_all(&self.type_id.0)?;
+            w.write_all(&0u16.to_le_bytes())?;
+        } else {
+            w.write_all(&self.type_id.0)?;
+            w.write_all(&(len as u16).to_le_bytes())?;
+        }
+        w.write_all(&self.data)
+    }
+
+    /// Null terminated string, decoded lossily.
+    pub fn zstring(&self) -> String {
+        let end = self.data.iter().position(|&c| c == 0).unwrap_or(self.data.len());
+        decode_string(&self.data[..end])
+    }
+
+    /// String field that holds a string table ID when the plugin is localised,
+    /// in the table for the field's type.
+    pub fn lstring(&self, localised: bool) -> LString {
+        match self.u32_at(0) {
+            Some(id) if localised && self.data.len() == 4 => LString::Id(id, StringsKind::of_field(&self.type_id)),
+            _ => LString::Inline(self.zstring())
+        }
+    }
+
+    pub fn u16_at(&self, offset: usize) -> Option<u16> {
+        self.data.get(offset..offset + 2).map(|b| u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32_at(&self, offset: usize) -> Option<u32> {
+        self.data.get(offset..offset + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub fn u64_at(&self, offset: usize) -> Option<u64> {
+        self.u32_at(offset).zip(self.u32_at(offset + 4)).map(|(lo, hi)| lo as u64 | (hi as u64) << 32)
+    }
+
+    pub fn f32_at(&self, offset: usize) -> Option<f32> {
+        self.u32_at(offset).map(f32::from_bits)
+    }
+}
+
+/// Split record data into its fields, in file order.
+///
+/// An `XXXX` field carries the size of the field that follows it and is not
+/// returned itself.
+pub fn parse_field_data(data: &[u8]) -> Result<Vec<Field<'_>>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    let mut oversize = None;
+    while pos < data.len() {
+        if data.len() - pos < 6 {
+            return Err(Error::unexpected(pos as u64, "field header", format!("{} bytes", data.len() - pos)))
+        }
+        let type_id = FourCC([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+        let mut size = u16::from_le_bytes([data[pos + 4], data[pos + 5]]) as usize;
+        pos += 6;
+        if let Some(xxxx) = oversize.take() { size = xxxx as usize; }
+        if data.len() - pos < size {
+            return Err(Error::unexpected(pos as u64, format!("{} bytes of {}", size, type_id), format!("{} bytes", data.len() - pos)))
+        }
+        if type_id == b"XXXX" {
+            if size != 4 { return Err(Error::unexpected(pos as u64, "4 byte XXXX field", format!("{} bytes", size))) }
+            oversize = Some(u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]));
+        } else {
+            fields.push(Field { type_id, data: Cow::Borrowed(&data[pos..pos + size]) });
+        }
+        pos += size;
+    }
+    if oversize.is_some() { return Err(Error::unexpected(pos as u64, "field after XXXX", "end of record")) }
+    Ok(fields)
 }
 
 //------------------------------------------------------------------------------
 
 #[derive(Debug)]
-pub struct WorldEntry {
-    pub world: Record,
-    pub world_children: WorldChildren
+pub struct WorldEntry<'a> {
+    pub world: Record<'a>,
+    pub world_children: Option<WorldChildren<'a>>
 }
 
 #[derive(Debug)]
-pub struct WorldChildren {
-    pub cell: Option<Cell>,
-    pub blocks: Vec<ExteriorCellBlock>
+pub struct WorldChildren<'a> {
+    pub header: GroupHeader,
+    /// Oblivion road network.
+    pub road: Option<Record<'a>>,
+    /// Persistent cell of the worldspace.
+    pub cell: Option<Cell<'a>>,
+    pub blocks: Vec<ExteriorCellBlock<'a>>
 }
 
 #[derive(Debug)]
-pub struct ExteriorCellBlock {
-    pub sub_blocks: Vec<ExteriorCellSubBlock>
+pub struct ExteriorCellBlock<'a> {
+    pub header: GroupHeader,
+    /// Block grid coordinates, each block covers 4x4 sub-blocks.
+    pub x: i16,
+    pub y: i16,
+    pub sub_blocks: Vec<ExteriorCellSubBlock<'a>>
 }
 
 #[derive(Debug)]
-pub struct ExteriorCellSubBlock {
-    pub cells: Vec<Cell>
+pub struct ExteriorCellSubBlock<'a> {
+    pub header: GroupHeader,
+    /// Sub-block grid coordinates, each sub-block covers 8x8 cells.
+    pub x: i16,
+    pub y: i16,
+    pub cells: Vec<Cell<'a>>
 }
 
 //------------------------------------------------------------------------------
 
 #[derive(Debug)]
-pub struct Cell {
-    pub cell: Record,
-    pub cell_children: Option<CellChildren>
+pub struct InteriorCellBlock<'a> {
+    pub header: GroupHeader,
+    /// Last digit of the cell form IDs.
+    pub index: i32,
+    pub sub_blocks: Vec<InteriorCellSubBlock<'a>>
 }
 
 #[derive(Debug)]
-pub struct CellChildren {
+pub struct InteriorCellSubBlock<'a> {
+    pub header: GroupHeader,
+    /// Second to last digit of the cell form IDs.
+    pub index: i32,
+    pub cells: Vec<Cell<'a>>
+}
+
+//------------------------------------------------------------------------------
+
+#[derive(Debug)]
+pub struct Cell<'a> {
+    pub cell: Record<'a>,
+    pub cell_children: Option<CellChildren<'a>>
+}
+
+#[derive(Debug)]
+pub struct CellChildren<'a> {
     pub parent_id: u32,
-    pub temporary: Option<Vec<Record>>,
-    pub persistant: Option<Vec<Record>>
+    pub header: GroupHeader,
+    pub temporary: Option<Vec<Record<'a>>>,
+    pub persistant: Option<Vec<Record<'a>>>,
+    pub visible_distant: Option<Vec<Record<'a>>>,
+    /// Headers of the subgroups above, in file order.
+    pub subgroups: Vec<GroupHeader>
 }
 
 #[derive(Debug)]
@@ -104,34 +382,85 @@ pub struct CellTemporaryChildren {
 //------------------------------------------------------------------------------
 
 #[derive(Debug)]
-pub struct Dialog {
-    pub dialog: Record,
-    pub children: Option<Group>
+pub struct Dialog<'a> {
+    pub dialog: Record<'a>,
+    /// Topic children group holding the INFO records.
+    pub children: Option<Group<'a>>
 }
 
 //------------------------------------------------------------------------------
 
 #[derive(Debug)]
-pub struct Quest {
-    pub quest: Record,
-    pub children: Option<Group>
+pub struct Quest<'a> {
+    pub quest: Record<'a>,
+    /// Decoded stages, objectives and aliases of the QUST record.
+    pub data: QuestData,
+    /// Quest owned dialogue, scenes and so on.
+    pub children: Option<Group<'a>>
+}
+
+#[derive(Debug)]
+pub struct GameSetting<'a> {
+    pub setting: Record<'a>,
+    /// EDID, whose prefix decides the type of the value.
+    pub editor_id: String,
+    /// Decoded DATA.
+    pub value: GameSettingValue
+}
+
+#[derive(Debug)]
+pub struct Keyword<'a> {
+    pub keyword: Record<'a>,
+    /// EDID
+    pub editor_id: Option<String>,
+    /// CNAM colour as red, green, blue and unused bytes.
+    pub color: Option<[u8; 4]>,
+    /// TNAM keyword type (Fallout 4 and later).
+    pub kind: Option<u32>
+}
+
+impl<'a> GameSetting<'a> {
+    /// Decode the value of a GMST record, leaving localised strings as string table IDs.
+    pub fn from_record(setting: Record<'a>, localised: bool) -> GameSetting<'a> {
+        let editor_id = setting.field(b"EDID").map(Field::zstring).unwrap_or_default();
+        let value = setting.field(b"DATA")
+            .map_or(GameSettingValue::Unknown(Vec::new()), |data| GameSettingValue::decode(&editor_id, data, localised));
+        GameSetting { setting, editor_id, value }
+    }
+}
+
+impl<'a> Keyword<'a> {
+    /// Decode the fields of a KYWD record.
+    pub fn from_record(keyword: Record<'a>) -> Keyword<'a> {
+        let editor_id = keyword.field(b"EDID").map(Field::zstring);
+        let color = keyword.field(b"CNAM")
+            .and_then(|field| field.data.get(0..4))
+            .map(|c| [c[0], c[1], c[2], c[3]]);
+        let kind = keyword.field(b"TNAM").and_then(|field| field.u32_at(0));
+        Keyword { keyword, editor_id, color, kind }
+    }
 }
 
 //------------------------------------------------------------------------------
 
-macro_rules! indent {
-    ($parser:expr, $($arg:tt)*) => {
-        let indent = " ".repeat($parser.depth() as usize * 2);
-        print!("{}{}", indent, format!($($arg)*));
-    };
+/// A whole parsed plugin.
+pub struct Plugin<'a> {
+    /// TES4 record.
+    pub header: Record<'a>,
+    pub file_header: FileHeader,
+    /// Top groups with their headers, in file order.
+    pub groups: Vec<(GroupHeader, TopGroup<'a>)>
 }
-macro_rules! indentln {
-    ($parser:expr, $($arg:tt)*) => {
-        let indent = " ".repeat($parser.depth() as usize * 2);
-        println!("{}{}", indent, format!($($arg)*));
-    };
+
+impl<'a> Plugin<'a> {
+    /// Every record except the TES4 record, in file order.
+    pub fn records(&self) -> impl Iterator<Item = &Record<'a>> {
+        self.groups.iter().flat_map(|(_, group)| group.records())
+    }
 }
 
+//------------------------------------------------------------------------------
+
 /*
 /// Elder Scrolls Master parser implementation.
 impl<R> ESMParser<R> where R: std::io::Read + std::io::Seek {
@@ -2076,7 +2405,7 @@ impl<R> ESMParser<R> where R: std::io::Read + std::io::Seek {
 //------------------------------------------------------------------------------
 
 
-impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
+impl<'a, R> ESMParser2<R> where R: Source<'a> {
     
     pub fn parse_until<F>(&mut self, limit: u64, f: fn (&mut Self) -> Result<F>) -> Result<Vec<F>> {
         let mut out = Vec::new();
@@ -2086,6 +2415,20 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
         Ok(out)
     }
 
+    /// Run `f` one level deeper, named `name` in error paths if given.
+    ///
+    /// The depth and path are restored whether `f` succeeds or fails, so the
+    /// parser can be reused after an error.
+    fn nested<T>(&mut self, name: Option<String>, f: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        let path_len = self.path.len();
+        if let Some(name) = name { self.path.push(name); }
+        self.push();
+        let result = f(self);
+        self.pop();
+        self.path.truncate(path_len);
+        result
+    }
+
     pub fn parse_top_level(&mut self) -> Result<()> {
 
         // Get file size
@@ -2095,7 +2438,7 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
         self.reader().seek(std::io::SeekFrom::Start(0))?;
 
         // Parse the FileHeader record
-        let _header_record = self.parse_record()?;
+        let _header = self.parse_file_header()?;
 
         // Parse the rest of the record groups
         self.parse_until(total_size, Self::parse_top_group)?;
@@ -2103,192 +2446,209 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
         Ok(())
     }
 
-    pub fn parse_record(&mut self) -> Result<Record> {
-        let header: RecordHeader = self.read()?;
-        indentln!(self, "{:?}", header);
-        if header.type_id == b"GRUP" {
-            panic!("Unexpected GRUP record: {:?}", header);
-        }
-        
-        // TODO handle decrompression
-        if header.flags & 0x40000000 != 0 {
-            self.skip(header.size as u64)?;
-        } else {
-            match header.type_id.0 {
+    /// Game detected by `detect_game` or `parse_file_header`.
+    pub fn game(&self) -> Game {
+        self.game
+    }
 
-                _ => {
-                    self.skip(header.size as u64)?;
-                }
+    /// Detect the game from the TES4 record at the start of the file.
+    pub fn detect_game(&mut self) -> Result<Game> {
+        self.reader().seek(std::io::SeekFrom::Start(0))?;
+        let mut bytes = [0u8; Game::DETECT_SIZE];
+        let mut read = 0;
+        // a short file is left to `Game::detect`, so read until full or at the end
+        while read < bytes.len() {
+            match self.reader().read(&mut bytes[read..])? {
+                0 => break,
+                n => read += n
             }
-    
         }
-        
-        
-        Ok(Record { header, fields: Vec::new() })
+        self.reader().seek(std::io::SeekFrom::Start(0))?;
+        self.game = match Game::detect(&bytes[..read]) {
+            Some(game) => game,
+            None => return Err(self.unexpected("TES4 record with HEDR", format!("{:?}", &bytes[..read.min(4)])))
+        };
+        Ok(self.game)
     }
 
-    pub fn parse_subgroup_record(&mut self) -> Result<Record> {
-        let header: RecordHeader = self.read()?;
-        indentln!(self, "{:?}", header);
-        match &header.type_id.0 {
-            b"REFR" | b"ACHR" | b"PHZD" | b"LAND" | b"NAVM" | b"PGRE" | b"PMIS" | b"ACRE" => {
-                // TODO handle decrompression
-                if header.flags & 0x40000000 != 0 {
-                    self.skip(header.size as u64)?;
-                } else {
-                    match header.type_id.0 {
+    /// Parse the TES4 record at the start of the file.
+    pub fn parse_file_header(&mut self) -> Result<FileHeader> {
+        Ok(self.parse_tes4()?.1)
+    }
 
-                        _ => {
-                            self.skip(header.size as u64)?;
-                        }
-                    }
-            
-                }
-                
-                
-                Ok(Record { header, fields: Vec::new() })
-            }
-            _ => {
-                panic!("Unexpected record type: {:?}", header);
-            }
+    /// Parse the TES4 record at the start of the file, keeping the record.
+    fn parse_tes4(&mut self) -> Result<(Record<'a>, FileHeader)> {
+        self.detect_game()?;
+        let record = self.parse_record()?;
+        let header = FileHeader::from_record(&record, self.game)?;
+        self.localised = header.flags.is_localised_for(self.game);
+        Ok((record, header))
+    }
+
+    /// Parse the whole plugin, keeping the top group headers.
+    pub fn parse_plugin(&mut self) -> Result<Plugin<'a>> {
+        let total_size = self.reader().seek(std::io::SeekFrom::End(0))?;
+        let (header, file_header) = self.parse_tes4()?;
+        let groups = self.parse_until(total_size, |parser| {
+            let group_header = parser.peek_group_header()?;
+            let group = parser.parse_top_group()?;
+            // parse_top_group has already failed on anything else
+            Ok((group_header.expect("top group header"), group))
+        })?;
+        Ok(Plugin { header, file_header, groups })
+    }
+
+    /// Use string tables to resolve localised strings.
+    pub fn set_strings(&mut self, strings: StringTables) {
+        self.strings = Some(std::sync::Arc::new(strings));
+    }
+
+    /// Keep the raw data of compressed records in `Record::compressed`, for
+    /// writing them back byte for byte with `EsmWriter`.
+    ///
+    /// Off by default, as it holds on to the compressed and inflated data of
+    /// every compressed record.
+    pub fn keep_compressed(&mut self, keep: bool) {
+        self.keep_compressed = keep;
+    }
+
+    /// Decode a potentially localised string field.
+    pub fn lstring(&self, field: &Field) -> LString {
+        field.lstring(self.localised)
+    }
+
+    /// Text of a potentially localised string field, looked up in the string tables if needed.
+    pub fn text(&self, field: &Field) -> Option<String> {
+        match self.lstring(field) {
+            LString::Inline(text) => Some(text),
+            LString::Id(id, kind) => self.strings.as_ref()?.get(kind, id).map(str::to_owned)
         }
-        
-        
     }
 
-    pub fn parse_group(&mut self) -> Result<Group> {
-        let header: GroupHeader = self.read()?;
-        let content_size = header.size as u64 - 24;
-        let content_end = self.reader().stream_position()? + content_size;
+    /// Error for unexpected input at the current position.
+    fn unexpected(&mut self, expected: &str, found: String) -> Error {
+        let offset = self.reader().stream_position().unwrap_or(0);
+        Error::Unexpected { offset, path: self.path.join("/"), expected: expected.to_owned(), found }
+    }
 
-        let label = header.get_label();
+    pub fn parse_record(&mut self) -> Result<Record<'a>> {
+        let header = self.read_record_header()?;
+        if header.type_id == b"GRUP" {
+            return Err(self.unexpected("record", format!("{:?}", header)))
+        }
+        self.parse_record_data(header)
+    }
 
-        if header.type_id != b"GRUP" {
-            panic!("Expected GRUP record got: {:?} instead", header.type_id);
+    pub fn parse_subgroup_record(&mut self) -> Result<Record<'a>> {
+        let header = self.read_record_header()?;
+        if self.game.cell_child_types().contains(&&header.type_id.0) {
+            self.parse_record_data(header)
+        } else {
+            Err(self.unexpected("cell child record", format!("{:?}", header)))
         }
+    }
 
-        match label {
-            // Top Group
-            GroupLabel::Top(type_id) => {
-                match &type_id.0 {
-                    b"WRLD" => {
-                        indentln!(self, "{:?}", label);
-                        self.push();
-                        self.parse_until(content_end, Self::parse_world_entry)?;
-                        self.pop();
-                        
-                    }
-                    b"CELL" => {
-                        self.push();
-                        self.parse_until(content_end, Self::parse_group)?;
-                        self.pop();
-                    }
-                    b"QUST" => {
-                        self.push();
-                        self.skip(header.size as u64 - 24)?;
-                        self.pop();
-                    }
-                    b"DIAL" => {
-                        indentln!(self, "{:?}", header);
-                        self.push();
-                        //self.skip(header.size as u64 - 24)?;
-                        while self.reader().stream_position()? < content_end {
-                            let _dialog = self.parse_record()?;
-
-                            //indentln!(self, "{:?}", dialog);
-
-                            let next_header: GroupHeader = self.read()?;
-                            self.reader.seek_relative(-24)?;
-
-                            if &next_header.type_id.0 == b"GRUP" && next_header.group_type == GroupLabelType_TopicChildren {
-                                self.push();
-                                let _children = self.parse_group()?;
-                                self.pop();
-                            }
-
-                        }
-                        self.pop();
-                    }
-                    _ => {
-                        indentln!(self, "{:?}", label);
-                        self.push();
-                        // Attempt to parse the non-custom groups which are just a list of records
-                        //let _records = self.parse_records(content_size)?;
-                        let _records = self.parse_until(content_end, Self::parse_record)?;
-                        self.pop();
-                    }
+    /// Parse the fields of a record whose header has already been read.
+    fn parse_record_data(&mut self, header: RecordHeader) -> Result<Record<'a>> {
+        let start = self.reader().stream_position()?;
+        let mut compressed = None;
+        let data = if header.flags & RECORD_FLAG_COMPRESSED != 0 {
+            let raw = self.reader().read_bytes(header.size as usize)?;
+            let inflated = self.inflated.as_mut().and_then(|inflated| inflated.remove(&start));
+            let data = match inflated {
+                Some(data) => data,
+                None => {
+                    let path = self.path.join("/");
+                    inflate_record(&raw).map_err(|e| e.located(start, &path))?
                 }
+            };
+            if self.keep_compressed { compressed = Some(raw); }
+            Cow::Owned(data)
+        } else {
+            self.reader().read_bytes(header.size as usize)?
+        };
+        let fields = match data {
+            // fields of borrowed data borrow the same bytes
+            Cow::Borrowed(data) => parse_field_data(data),
+            Cow::Owned(data) => parse_field_data(&data).map(|fields| fields.into_iter().map(Field::into_owned).collect())
+        };
+        let fields = match fields {
+            Ok(fields) => fields,
+            Err(e) => {
+                // offsets in compressed records are relative to the inflated data
+                let path = format!("{}/{} {:08X}", self.path.join("/"), header.type_id, form_id(&header));
+                return Err(e.located(start, &path))
             }
-            // World Children
-            GroupLabel::WorldChildren(_) => {
-                panic!("WorldChildren is handled by another function, you should not see this.");
-            }
-            // Interior Cell Block
-            GroupLabel::InteriorCellBlock(_index) => {
-                self.skip(content_size)?;
-            }
-            // Interior Cell Sub-Block
-            GroupLabel::InteriorCellSubBlock(_index) => {
-                self.skip(content_size)?;
-            }
-            // Exterior Cell Block
-            GroupLabel::ExteriorCellBlock(coords) => {
-                indentln!(self, "Exterior Cell Block({:?})", coords);
-                self.push();
-                self.parse_until(content_end, Self::parse_group)?;
-                self.pop();
-            }
-            // Exterior Cell Sub-block
-            GroupLabel::ExteriorCellSubBlock(coords) => {
-                indentln!(self, "Exterior Cell Sub-Block({:?})", coords);
-                self.push();
-                let _cell = self.parse_until(content_end, Self::parse_cell)?;
-                self.pop();
-                // let next_id: GroupHeader = self.read()?;
-                // self.reader.seek_relative(-24)?;
-                // indentln!(self, "Next ID {:?}", next_id.try_get_label().unwrap());
-                //self.skip(size)?;
-            }
-            // Cell Children
-            GroupLabel::CellChildren(record_id) => {
-                indentln!(self, "Cell Children({:?})", record_id);
-                self.skip(content_size)?;
-            }
-            // Topic Children
-            GroupLabel::TopicChildren(_record_id) => {
-                //indentln!(self, "Topic Children({:?})", record_id);
-                self.skip(content_size)?;
-            }
-            // Cell Persistent Children
-            GroupLabel::CellPersistentChildren(_record_id) => {
-                self.skip(content_size)?;
-            }
-            // Cell Temporary Children
-            GroupLabel::CellTemporaryChildren(_record_id) => {
-                self.skip(content_size)?;
-            }
-            // Cell Visible Distant Children
-            GroupLabel::CellVisibleDistantChildren(_record_id) => {
-                self.skip(content_size)?;
-            }
-            GroupLabel::Unknown(_type_id) => {
-                self.skip(content_size)?;
-            }
+        };
+        Ok(Record { header, fields, compressed })
+    }
+
+    /// Read and decompress a compressed record body of `size` bytes.
+    fn deflate(&mut self, size: u32) -> Result<Vec<u8>> {
+        let start = self.reader().stream_position()?;
+        if size < 4 { return Err(self.unexpected("compressed record data", format!("{} bytes", size))) }
+        let uncompressed_size: u32 = self.read()?;
+        let compressed = self.reader().read_bytes(size as usize - 4)?;
+        let path = self.path.join("/");
+        inflate(&compressed, uncompressed_size).map_err(|e| e.located(start, &path))
+    }
+
+    /// Parse any group as a plain tree of records and subgroups.
+    pub fn parse_group(&mut self) -> Result<Group<'a>> {
+        let header = self.read_group_header()?;
+        let content_end = self.group_end(&header)?;
+
+        let label = header.get_label();
+        let children = self.nested(Some(format!("{:?}", label)), |parser| parser.parse_until(content_end, Self::parse_group_child))?;
+
+        Ok(Group { header, children })
+    }
+
+    /// Parse the next record or subgroup inside a group.
+    pub fn parse_group_child(&mut self) -> Result<GroupChild<'a>> {
+        if self.peek_group_header()?.is_some() {
+            Ok(GroupChild::Group(self.parse_group()?))
+        } else {
+            Ok(GroupChild::Record(self.parse_record()?))
         }
+    }
 
-        
+    /// Read a group header, checking that it is a GRUP.
+    fn read_group_header(&mut self) -> Result<GroupHeader> {
+        let mut bytes = [0u8; std::mem::size_of::<GroupHeader>()];
+        self.reader().read_exact(&mut bytes[..self.game.header_size() as usize])?;
+        let header: GroupHeader = unsafe { std::mem::transmute(bytes) };
+        if header.type_id != b"GRUP" {
+            return Err(self.unexpected("GRUP", header.type_id.to_string()))
+        }
+        if (header.size as u64) < self.game.header_size() {
+            return Err(self.unexpected("group larger than its header", header.size.to_string()))
+        }
+        Ok(header)
+    }
 
-        Ok(Group { header })
+    /// Read a record header, using the header size of the detected game.
+    ///
+    /// Shorter headers are zero filled, so the version control fields are zero for Oblivion.
+    fn read_record_header(&mut self) -> Result<RecordHeader> {
+        let mut bytes = [0u8; std::mem::size_of::<RecordHeader>()];
+        self.reader().read_exact(&mut bytes[..self.game.header_size() as usize])?;
+        Ok(unsafe { std::mem::transmute(bytes) })
     }
 
-    pub fn parse_top_group(&mut self) -> Result<TopGroup> {
-        let header: GroupHeader = self.read()?;
-        let limit = self.reader.stream_position()? + header.size as u64 - 24;
+    /// Stream position of the end of a group whose header has just been read.
+    fn group_end(&mut self, header: &GroupHeader) -> Result<u64> {
+        Ok(self.reader().stream_position()? + header.size as u64 - self.game.header_size())
+    }
 
-        println!("{:?} --------------------------------------------------", header.get_label());
-        
-        self.push();
+    pub fn parse_top_group(&mut self) -> Result<TopGroup<'a>> {
+        let header = self.read_group_header()?;
+        let limit = self.group_end(&header)?;
+        self.nested(Some(format!("{:?}", header.get_label())), |parser| parser.parse_top_group_records(&header, limit))
+    }
 
+    /// Records of a top group whose header has been read, up to `limit`.
+    fn parse_top_group_records(&mut self, header: &GroupHeader, limit: u64) -> Result<TopGroup<'a>> {
         let records;
 
         match header.get_label() {
@@ -2314,7 +2674,7 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
                     b"BOOK" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"BPTD" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"CAMS" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
-                    b"CELL" => { records = TopGroup::Cell(self.parse_until(limit, Self::parse_group)?); }
+                    b"CELL" => { records = TopGroup::Cell(self.parse_until(limit, Self::parse_interior_block)?); }
                     b"CLAS" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"CLFM" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"CLMT" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
@@ -2327,7 +2687,7 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
                     b"CSTY" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"DEBR" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"DFOB" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
-                    b"DIAL" => { self.seek(limit)?; records = TopGroup::Skipped }
+                    b"DIAL" => { records = TopGroup::Dialogue(self.parse_until(limit, Self::parse_dial)?); }
                     b"DLVW" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"DMGT" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"DOBJ" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
@@ -2346,7 +2706,7 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
                     b"FURN" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"GDRY" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"GLOB" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
-                    b"GMST" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
+                    b"GMST" => { records = TopGroup::GameSetting(self.parse_until(limit, Self::parse_gmst)?); }
                     b"GRAS" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"HAIR" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"HAZD" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
@@ -2361,7 +2721,7 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
                     b"IPDS" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"KEYM" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"KSSM" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
-                    b"KYWD" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
+                    b"KYWD" => { records = TopGroup::Keyword(self.parse_until(limit, Self::parse_kywd)?); }
                     b"LAYR" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"LCRT" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"LCTN" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
@@ -2396,7 +2756,7 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
                     b"PKIN" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"PROJ" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"PWAT" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
-                    b"QUST" => { self.skip(limit)?; records = TopGroup::Skipped }
+                    b"QUST" => { records = TopGroup::Quest(self.parse_until(limit, Self::parse_qust)?); }
                     b"RACE" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"REGN" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"RELA" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
@@ -2430,193 +2790,266 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
                     b"WTHR" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     b"ZOOM" => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
 
-                    _ => {
-                        panic!("Unexpected record type {}", id);
-                        self.seek(limit)?;
-                        indentln!(self, "Skipped");
-                        records = TopGroup::Skipped;
-                    }
+                    // Top groups of other games (SKIL, BSGN, CHAL, Starfield's AAPD, ...) are plain record lists
+                    _ => { records = TopGroup::Unhandled(self.parse_until(limit, Self::parse_record)?); }
                     
                 }
             },
-            _ => panic!("Expected top group, got: {:?}", header)
+            label => return Err(self.unexpected("top group", format!("{:?}", label)))
         }
 
-        self.pop();
         Ok(records)
     }
 
-    pub fn parse_cell(&mut self) -> Result<Cell> {
+    pub fn parse_interior_block(&mut self) -> Result<InteriorCellBlock<'a>> {
+        let header = self.read_group_header()?;
+        let limit = self.group_end(&header)?;
+        let index = match header.get_label() {
+            GroupLabel::InteriorCellBlock(index) => index as i32,
+            label => return Err(self.unexpected("interior cell block", format!("{:?}", label)))
+        };
+
+        let name = format!("Interior Cell Block({})", index);
+        let sub_blocks = self.nested(Some(name), |parser| parser.parse_until(limit, Self::parse_interior_sub_block))?;
+
+        Ok(InteriorCellBlock { header, index, sub_blocks })
+    }
+
+    pub fn parse_interior_sub_block(&mut self) -> Result<InteriorCellSubBlock<'a>> {
+        let header = self.read_group_header()?;
+        let limit = self.group_end(&header)?;
+        let index = match header.get_label() {
+            GroupLabel::InteriorCellSubBlock(index) => index as i32,
+            label => return Err(self.unexpected("interior cell sub-block", format!("{:?}", label)))
+        };
+
+        let name = format!("Interior Cell Sub-Block({})", index);
+        let cells = self.nested(Some(name), |parser| parser.parse_until(limit, Self::parse_cell))?;
+
+        Ok(InteriorCellSubBlock { header, index, cells })
+    }
+
+    pub fn parse_cell(&mut self) -> Result<Cell<'a>> {
         let cell = self.parse_record()?;
         let mut cell_children = None;
-        indentln!(self, "{:?}", cell.header);
 
-        let next_header: GroupHeader = self.read()?;
-        self.rewind(24)?;
+        let next_header = self.peek_group_header()?;
 
-        if next_header.type_id == b"GRUP" && next_header.group_type == GroupLabelType_CellChildren {
-            if let Ok(cc) = self.parse_cell_children() {
-                cell_children = Some(cc);
-            } else {
-                panic!("Could not parse CellChildren");
-            }
+        if next_header.is_some_and(|next_header| next_header.group_type == GroupLabelType_CellChildren) {
+            self.path.push(format!("CELL {:08X}", cell.form_id()));
+            let result = self.parse_cell_children();
+            self.path.pop();
+            cell_children = Some(result?);
         }
 
         Ok(Cell { cell, cell_children })
     }
 
-    pub fn parse_cell_children(&mut self) -> Result<CellChildren> {
-        self.push();
-        let header: GroupHeader = self.read()?;
+    pub fn parse_cell_children(&mut self) -> Result<CellChildren<'a>> {
+        let header = self.read_group_header()?;
+        let limit = self.group_end(&header)?;
         let label = header.get_label();
-        
-        
+        let parent_id = match label {
+            GroupLabel::CellChildren(parent_id) => parent_id,
+            _ => return Err(self.unexpected("cell children", format!("{:?}", label)))
+        };
 
-        match label {
-            GroupLabel::CellChildren(parent_id) => {
-                indentln!(self, "{:?}", label);
-                let mut temporary = None;
-                let mut persistant = None;
+        self.nested(None, |parser| parser.parse_cell_subgroups(parent_id, header, limit))
+    }
 
-                let next_header: GroupHeader = self.read()?;
-                let next_label = next_header.get_label();
-                let next_limit = self.reader.stream_position()? + next_header.size as u64 - 24;
+    /// Persistent, temporary and visible distant subgroups of a cell, up to `limit`.
+    fn parse_cell_subgroups(&mut self, parent_id: u32, header: GroupHeader, limit: u64) -> Result<CellChildren<'a>> {
+        let mut children = CellChildren {
+            parent_id, header, temporary: None, persistant: None, visible_distant: None, subgroups: Vec::new()
+        };
 
-                self.push();
+        // The subgroups may appear in any order and combination
+        while self.reader.stream_position()? < limit {
+            let next_header = self.read_group_header()?;
+            let next_limit = self.group_end(&next_header)?;
+            let next_label = next_header.get_label();
+            let slot = match next_label {
+                GroupLabel::CellPersistentChildren(_) => &mut children.persistant,
+                GroupLabel::CellTemporaryChildren(_) => &mut children.temporary,
+                GroupLabel::CellVisibleDistantChildren(_) => &mut children.visible_distant,
+                _ => return Err(self.unexpected("persistent, temporary or visible distant children", format!("{:?}", next_label)))
+            };
+            if slot.is_some() {
+                return Err(self.unexpected("one group of each kind", format!("second {:?}", next_label)))
+            }
+            *slot = Some(self.nested(None, |parser| parser.parse_until(next_limit, Self::parse_subgroup_record))?);
+            children.subgroups.push(next_header);
+        }
 
-                indentln!(self, "{:?}", next_label);
+        Ok(children)
+    }
 
-                match next_label {
-                    GroupLabel::CellPersistentChildren(_parent) => {
-                        persistant = Some(self.parse_until(next_limit, Self::parse_subgroup_record)?);
-                    }
-                    GroupLabel::CellTemporaryChildren(_parent) => {
-                        temporary = Some(self.parse_until(next_limit, Self::parse_subgroup_record)?);
-                    }
-                    _ => {
-                        // If CellChildren exists, there should be at least one sub group
-                        // Panic if the sub group is not correct
-                        panic!("Unexpected group type encountered inside CellChildren: {:?}", header);
-                    }
-                }
+    pub fn parse_world_entry(&mut self) -> Result<WorldEntry<'a>> {
 
+        let world = self.parse_record()?;
 
-                let next_header: GroupHeader = self.read()?;
+        // A worldspace without cells has no children group
+        let mut world_children = None;
+        if let Some(next_header) = self.peek_group_header()? {
+            if let GroupLabel::WorldChildren(_) = next_header.get_label() {
+                self.path.push(format!("WRLD {:08X}", world.form_id()));
+                let result = self.parse_world_children();
+                self.path.pop();
+                world_children = Some(result?);
+            }
+        }
 
-                // Rewind if next group is not actually a group
-                if &next_header.type_id.0 != b"GRUP" {
-                    self.rewind(24)?;
-                    
-                    let out  = CellChildren { parent_id, temporary, persistant };
-                    //indentln!(self, "{:?}", out);
-                    self.pop();
-                    self.pop();
-                    Ok(out)
+        Ok(WorldEntry { world, world_children })
+    }
 
-                
-                } else {
-                    let next_label = next_header.get_label();
-                    let next_limit = self.reader().stream_position()? + next_header.size as u64 - 24;
+    pub fn parse_world_children(&mut self) -> Result<WorldChildren<'a>> {
+        self.nested(None, |parser| {
+            let header = parser.read_group_header()?;
+            let limit = parser.group_end(&header)?;
+            let label = header.get_label();
+            if !matches!(label, GroupLabel::WorldChildren(_)) {
+                return Err(parser.unexpected("world children", format!("{:?}", label)))
+            }
 
-                    match next_label {
-                        GroupLabel::CellTemporaryChildren(_parent_id) => {
-                            indentln!(self, "{:?}", next_label);
-                            //self.skip(next_header.size as u64 - 24)?;
-                            temporary = Some(self.parse_until(next_limit, Self::parse_subgroup_record)?);
-                        }
-                        GroupLabel::CellPersistentChildren(_parent_id) => {
-                            indentln!(self, "{:?}", next_label);
-                            //self.skip(next_header.size as u64 - 24)?;
-                            persistant = Some(self.parse_until(next_limit, Self::parse_subgroup_record)?);
-                        }
-                        _ => {
-                            // Next group does not belong to cell children, rewind and continue
-                            self.rewind(24)?;
-                        }
+            // Records before the blocks, Oblivion puts a ROAD ahead of the persistent cell
+            let (road, cell) = parser.nested(None, |parser| {
+                let mut road = None;
+                let mut cell = None;
+                while parser.reader.stream_position()? < limit && parser.peek_group_header()?.is_none() {
+                    let next_id: FourCC = parser.read()?;
+                    parser.rewind(4)?;
+                    if &next_id.0 == b"ROAD" {
+                        road = Some(parser.parse_record()?);
+                    } else {
+                        cell = Some(parser.parse_cell()?);
                     }
-
-                    let out = CellChildren { parent_id, temporary, persistant };
-                    //indentln!(self, "{:?}", out);
-                    self.pop();
-                    self.pop();
-                    Ok(out)
                 }
+                Ok((road, cell))
+            })?;
 
-                
-            }
-            _ => {
-                panic!("Tried to parse CellChildren, got different group type: {:?}", header);
-            }
-        }
-
+            let blocks = parser.parse_until(limit, Self::parse_exterior_block)?;
+            Ok(WorldChildren { header, road, cell, blocks })
+        })
     }
 
-    pub fn parse_world_entry(&mut self) -> Result<WorldEntry> {
-
-        let world = self.parse_record()?;
-        indentln!(self, "{:?}", world.header);
+    pub fn parse_exterior_block(&mut self) -> Result<ExteriorCellBlock<'a>> {
+        let header = self.read_group_header()?;
+        let limit = self.group_end(&header)?;
+        if !matches!(header.get_label(), GroupLabel::ExteriorCellBlock(_)) {
+            return Err(self.unexpected("exterior cell block", format!("{:?}", header.get_label())))
+        }
+        let (x, y) = group_grid(&header);
 
-        let world_children = self.parse_world_children()?;
+        let name = format!("Exterior Cell Block({}, {})", x, y);
+        let sub_blocks = self.nested(Some(name), |parser| parser.parse_until(limit, Self::parse_exterior_sub_block))?;
 
-        Ok(WorldEntry { world, world_children })
+        Ok(ExteriorCellBlock { header, x, y, sub_blocks })
     }
 
-    pub fn parse_world_children(&mut self) -> Result<WorldChildren> {
-        self.push();
-        let header: GroupHeader = self.read()?;
-        let limit = self.reader.stream_position()? + header.size as u64 - 24;
-        let label = header.get_label();
-        
-        indentln!(self, "{:?}", label);
-        self.push();
+    pub fn parse_exterior_sub_block(&mut self) -> Result<ExteriorCellSubBlock<'a>> {
+        let header = self.read_group_header()?;
+        let limit = self.group_end(&header)?;
+        if !matches!(header.get_label(), GroupLabel::ExteriorCellSubBlock(_)) {
+            return Err(self.unexpected("exterior cell sub-block", format!("{:?}", header.get_label())))
+        }
+        let (x, y) = group_grid(&header);
 
-        let next_id: FourCC = self.read()?;
-        self.rewind(4)?;
+        let name = format!("Exterior Cell Sub-Block({}, {})", x, y);
+        let cells = self.nested(Some(name), |parser| parser.parse_until(limit, Self::parse_cell))?;
 
-        
+        Ok(ExteriorCellSubBlock { header, x, y, cells })
+    }
 
-        let cell = if &next_id.0 == b"CELL" {
-            Some(self.parse_cell()?)
-        } else {
-            None
-        };
+    /// Look at the next group header without consuming it, if the next entry is a group.
+    fn peek_group_header(&mut self) -> Result<Option<GroupHeader>> {
+        let start = self.reader().stream_position()?;
+        let size = self.game.header_size() as usize;
+        let mut bytes = [0u8; std::mem::size_of::<GroupHeader>()];
+        let result = self.reader().read_exact(&mut bytes[..size]);
+        match result {
+            // a relative seek back keeps the buffer of a buffered reader
+            Ok(()) => self.reader().seek_relative(-(size as i64))?,
+            Err(e) => {
+                self.reader().seek(std::io::SeekFrom::Start(start))?;
+                if e.kind() == std::io::ErrorKind::UnexpectedEof { return Ok(None) }
+                return Err(e.into())
+            }
+        }
+        if &bytes[..4] != b"GRUP" { return Ok(None) }
+        Ok(Some(unsafe { std::mem::transmute::<[u8; std::mem::size_of::<GroupHeader>()], GroupHeader>(bytes) }))
+    }
 
-        
+    /// Read `total_size` bytes of fields and pass each one to `f`, with `XXXX` sizes applied.
+    pub fn parse_fields(&mut self, f: FieldParser<Self>, total_size: u32) -> Result<()> {
+        let start = self.reader().stream_position()?;
+        let data = self.reader().read_bytes(total_size as usize)?;
+        let path = self.path.join("/");
+        let fields = parse_field_data(&data).map_err(|e| e.located(start, &path))?;
+        self.nested(None, |parser| fields.iter().try_for_each(|field| f(parser, field)))
+    }
 
-        // TODO Push correct values to vector
-        let blocks = Vec::new();
+    /// Parse a DIAL record and its topic children.
+    pub fn parse_dial(&mut self) -> Result<Dialog<'a>> {
+        let dialog = self.parse_record()?;
+        if dialog.header.type_id != b"DIAL" {
+            return Err(self.unexpected("DIAL record", dialog.header.type_id.to_string()))
+        }
 
-        self.pop();
-        let _blocks = self.parse_until(limit, Self::parse_group)?;
+        let mut children = None;
+        if let Some(next_header) = self.peek_group_header()? {
+            if next_header.group_type == GroupLabelType_TopicChildren {
+                let name = format!("DIAL {:08X}", dialog.form_id());
+                children = Some(self.nested(Some(name), Self::parse_group)?);
+            }
+        }
 
-        self.pop();
-        Ok(WorldChildren { cell, blocks })
+        Ok(Dialog { dialog, children })
     }
 
-    pub fn parse_fields(&mut self, f: FieldParser<Self>, total_size: u32) -> Result<()> {
-        if total_size == 0 { return Ok(()) }
-        let loop_end = self.reader().stream_position()? + total_size as u64;
-        self.push();
-        match loop {
-            let header: FieldHeader = self.read()?;
-            let start = self.reader().stream_position()?;
-            let size = header.size as u64;
-            f(self, &header)?; // parse the contents
-            let end = start + size;
-            let pos = self.reader().stream_position()?;
-            if pos == loop_end { break Ok(()) } // function consumed chunk
-            else if pos != end { return Err(chunk_parser::Error::ParseError) } // function made a mistake
-        } {
-            res => { self.pop(); res }
+    /// Parse a GMST record and decode its value.
+    ///
+    /// Localised string settings are resolved when string tables have been set.
+    pub fn parse_gmst(&mut self) -> Result<GameSetting<'a>> {
+        let setting = self.parse_record()?;
+        if setting.header.type_id != b"GMST" {
+            return Err(self.unexpected("GMST record", setting.header.type_id.to_string()))
+        }
+        let mut setting = GameSetting::from_record(setting, self.localised);
+        if let GameSettingValue::String(LString::Id(id, kind)) = setting.value {
+            if let Some(text) = self.strings.as_ref().and_then(|strings| strings.get(kind, id)) {
+                setting.value = GameSettingValue::String(LString::Inline(text.to_owned()));
+            }
         }
+        Ok(setting)
     }
 
-    pub fn parse_dial(&mut self) -> Result<Dialog> {
-        todo!()
+    /// Parse a KYWD record.
+    pub fn parse_kywd(&mut self) -> Result<Keyword<'a>> {
+        let keyword = self.parse_record()?;
+        if keyword.header.type_id != b"KYWD" {
+            return Err(self.unexpected("KYWD record", keyword.header.type_id.to_string()))
+        }
+        Ok(Keyword::from_record(keyword))
     }
 
-    pub fn parse_qust(&mut self) -> Result<Quest> {
-        todo!()
+    /// Parse a QUST record and its child group.
+    pub fn parse_qust(&mut self) -> Result<Quest<'a>> {
+        let quest = self.parse_record()?;
+        if quest.header.type_id != b"QUST" {
+            return Err(self.unexpected("QUST record", quest.header.type_id.to_string()))
+        }
+        let data = QuestData::decode(&quest, self.localised);
+
+        // Any group other than the next top group belongs to the quest
+        let mut children = None;
+        if let Some(next_header) = self.peek_group_header()? {
+            if !matches!(next_header.get_label(), GroupLabel::Top(_)) {
+                let name = format!("QUST {:08X}", quest.form_id());
+                children = Some(self.nested(Some(name), Self::parse_group)?);
+            }
+        }
+
+        Ok(Quest { quest, data, children })
     }
 }
 
@@ -2624,7 +3057,11 @@ impl<R> ESMParser2<R> where R: std::io::Read + std::io::Seek {
 
 pub mod prelude {
     pub use chunk_parser::prelude::*;
-    pub use {super::ESMParser, super::ESMParser2};
+    pub use {super::ESMParser, super::ESMParser2, super::TES3Parser, super::FileHeader, super::Game, super::MasterKind};
+    pub use {super::LString, super::StringTables, super::FormIdIndex, super::Source, super::Owned};
+    pub use {super::EsmVisitor, super::Walk, super::WalkContext, super::TreePrinter};
+    pub use {super::Plugin, super::EsmWriter, super::PluginBuilder};
+    pub use super::Error;
 }
 
 //==============================================================================
@@ -2633,24 +3070,850 @@ pub mod prelude {
 mod tests {
     use super::prelude::*;
 
-    //#[test]
-    fn zeta() -> chunk_parser::Result<()> {
-        const DATA: &[u8] = include_bytes!("../data/Zeta.esm");
-        let mut esm = ESMParser2::cursor(DATA);
-        esm.parse_top_level()
+    // Game files are not part of the repository, these run once they are
+    // copied into data/ and pass without checking anything otherwise.
+
+    /// A game data file, or `None` to skip a test when it is not installed.
+    fn game_data(path: &str) -> super::Result<Option<Vec<u8>>> {
+        if !std::path::Path::new(path).exists() { return Ok(None) }
+        Ok(Some(std::fs::read(path)?))
+    }
+
+    #[test]
+    fn zeta() -> super::Result<()> {
+        let Some(data) = game_data("data/Zeta.esm")? else { return Ok(()) };
+        ESMParser2::slice(&data).parse_top_level()
+    }
+
+    #[test]
+    fn fallout4() -> super::Result<()> {
+        let Some(data) = game_data("data/Fallout4.esm")? else { return Ok(()) };
+        ESMParser2::slice(&data).parse_top_level()
+    }
+
+    #[test]
+    fn fallout3() -> super::Result<()> {
+        let Some(data) = game_data("data/Fallout3.esm")? else { return Ok(()) };
+        ESMParser2::slice(&data).parse_top_level()
+    }
+
+    #[test]
+    fn oversized_field() -> super::Result<()> {
+        let big = field(b"OFST", &[7; 70000]);
+        let edid = field(b"EDID", b"Test\0");
+        let mut data = Vec::new();
+        edid.write_to(&mut data)?;
+        big.write_to(&mut data)?;
+        assert_eq!(data.len(), edid.encoded_len() + big.encoded_len());
+        let fields = super::parse_field_data(&data)?;
+        assert_eq!(fields.len(), 2);
+        assert!(fields[1].type_id == b"OFST");
+        assert_eq!(fields[1].data.len(), 70000);
+
+        // and through the parser
+        let mut builder = PluginBuilder::new(Game::Fallout4);
+        let misc = builder.add_record(b"MISC", vec![edid, big])?;
+        let data = plugin_bytes(&builder.build())?;
+        let plugin = ESMParser2::slice(&data).parse_plugin()?;
+        let record = plugin.records().find(|record| record.form_id() == misc).unwrap();
+        assert_eq!(record.fields.len(), 2);
+        assert_eq!(record.field(b"OFST").unwrap().data.len(), 70000);
+
+        // and through parse_fields
+        let mut data = Vec::new();
+        for field in &record.fields { field.write_to(&mut data)?; }
+        ESMParser2::slice(&data).parse_fields(|_, field| {
+            assert!(field.type_id == b"EDID" || field.data.len() == 70000);
+            Ok(())
+        }, data.len() as u32)?;
+        let mut esm = ESMParser2::slice(&data);
+        assert!(esm.parse_fields(|_, _| Err(super::Error::invalid("stop")), data.len() as u32).is_err());
+        assert_eq!(esm.depth(), 0);
+        Ok(())
     }
 
-    //#[test]
-    fn fallout4() -> chunk_parser::Result<()> {
-        const DATA: &[u8] = include_bytes!("../data/Fallout4.esm");
-        let mut esm = ESMParser2::cursor(DATA);
-        esm.parse_top_level()
+    #[test]
+    fn inflate_bounds() -> super::Result<()> {
+        use flate2::{write::ZlibEncoder, Compression};
+        use std::io::Write;
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[7; 1000])?;
+        let compressed = encoder.finish()?;
+
+        assert_eq!(super::inflate(&compressed, 1000)?, vec![7; 1000]);
+        // inflation stops one byte past the declared size
+        assert!(super::inflate(&compressed, 10).is_err());
+        assert!(super::inflate(&compressed, u32::MAX).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn file_header() -> super::Result<()> {
+        use super::{FileFlags, FileHeader};
+
+        let mut builder = PluginBuilder::new(Game::SkyrimSE);
+        builder.flags(FileFlags(0x81)).description("Test").add_master("Skyrim.esm", 250_000_000)?.add_master("Update.esm", 0x1_0000_0000)?;
+        let mut plugin = builder.build();
+
+        let header = FileHeader::from_record(&plugin.header, Game::SkyrimSE)?;
+        assert!(header.flags.is_master() && header.flags.is_localised());
+        assert_eq!(header.description.as_deref(), Some("Test"));
+        assert_eq!(header.master_names().collect::<Vec<_>>(), ["Skyrim.esm", "Update.esm"]);
+        assert_eq!(header.masters[0].size, 250_000_000);
+        assert_eq!(header.masters[1].size, 0x1_0000_0000);
+
+        let data = plugin_bytes(&plugin)?;
+        let mut esm = ESMParser2::slice(&data);
+        assert_eq!(esm.parse_file_header()?.masters.len(), 2);
+        assert!(esm.localised);
+
+        // the same flag means nothing before Skyrim
+        let mut builder = PluginBuilder::new(Game::Fallout3);
+        builder.flags(FileFlags(0x81));
+        let data = plugin_bytes(&builder.build())?;
+        let mut esm = ESMParser2::slice(&data);
+        esm.parse_file_header()?;
+        assert!(!esm.localised);
+
+        // a DATA field too short for a size, then no HEDR at all
+        plugin.header.fields.last_mut().unwrap().data = vec![0; 4].into();
+        assert!(FileHeader::from_record(&plugin.header, Game::SkyrimSE).is_err());
+        plugin.header.fields.retain(|field| field.type_id != b"HEDR" && field.type_id != b"DATA");
+        assert!(FileHeader::from_record(&plugin.header, Game::SkyrimSE).is_err());
+        Ok(())
     }
 
     #[test]
-    fn fallout3() -> chunk_parser::Result<()> {
-        const DATA: &[u8] = include_bytes!("../data/Fallout3.esm");
-        let mut esm = ESMParser2::cursor(DATA);
-        esm.parse_top_level()
+    fn reuse_after_error() -> super::Result<()> {
+        use std::io::Seek;
+
+        let mut builder = PluginBuilder::new(Game::Fallout4);
+        builder.add_record(b"MISC", vec![])?;
+        let room = builder.add_interior_cell(vec![])?;
+        builder.add_reference(room, b"REFR", false, vec![field(b"NAME", &[0; 4])])?;
+        let mut data = plugin_bytes(&builder.build())?;
+
+        // turn the temporary children into topic children, which a cell cannot have
+        let temporary = (0..data.len() - 16).find(|&i| {
+            &data[i..i + 4] == b"GRUP" && data[i + 8..i + 12] == room.to_le_bytes() && data[i + 12..i + 16] == 9u32.to_le_bytes()
+        }).unwrap();
+        data[temporary + 12] = 7;
+
+        let mut esm = ESMParser2::slice(&data);
+        match esm.parse_plugin() {
+            Err(super::Error::Unexpected { path, .. }) => assert!(path.contains(&format!("CELL {:08X}", room))),
+            _ => panic!("expected a cell children error")
+        }
+        assert_eq!(esm.depth(), 0);
+        assert!(esm.path.is_empty());
+
+        esm.reader().seek(std::io::SeekFrom::Start(0))?;
+        esm.parse_file_header()?;
+        assert!(matches!(esm.parse_top_group()?, super::TopGroup::Unhandled(records) if records.len() == 1));
+        assert!(esm.parse_top_group().is_err());
+        assert_eq!(esm.depth(), 0);
+        assert!(esm.path.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn quest_data() -> super::Result<()> {
+        use super::{QuestAliasKind, TopGroup};
+        let ctda = |function: u16| {
+            let mut data = vec![0; 32];
+            data[8..10].copy_from_slice(&function.to_le_bytes());
+            field(b"CTDA", &data)
+        };
+
+        let mut builder = PluginBuilder::new(Game::SkyrimSE);
+        builder.add_record(b"QUST", vec![
+            field(b"EDID", b"MQ01\0"), field(b"FULL", b"Main\0"), ctda(1),
+            field(b"INDX", &[10, 0, 1, 0]),
+            field(b"QSDT", &[1]), ctda(2), field(b"CNAM", b"First\0"), field(b"NAM0", &0x900u32.to_le_bytes()),
+            field(b"QSDT", &[0]), field(b"CNAM", b"Second\0"),
+            field(b"INDX", &[20, 0, 0, 0]),
+            field(b"QOBJ", &[5, 0]), field(b"FNAM", &1u32.to_le_bytes()), field(b"NNAM", b"Go\0"), ctda(3),
+            field(b"QSTA", &[2, 0, 0, 0, 4, 0, 0, 0]), ctda(4), ctda(5),
+            field(b"ALST", &2u32.to_le_bytes()), field(b"ALID", b"Boss\0"), field(b"FNAM", &8u32.to_le_bytes()), ctda(6), field(b"ALED", &[]),
+            field(b"ALLS", &3u32.to_le_bytes()), field(b"ALED", &[]),
+            ctda(7)
+        ])?;
+        let plugin = builder.build();
+        let Some((_, TopGroup::Quest(quests))) = plugin.groups.first() else { panic!("QUST group") };
+        let quest = &quests[0].data;
+        let functions = |conditions: &[super::Condition]| conditions.iter().map(|c| c.function().unwrap()).collect::<Vec<_>>();
+
+        assert_eq!(quest.editor_id.as_deref(), Some("MQ01"));
+        assert_eq!(quest.name, Some(LString::Inline("Main".into())));
+        assert_eq!(functions(&quest.conditions), [1, 7]);
+
+        assert_eq!(quest.stages.len(), 2);
+        let stage = &quest.stages[0];
+        assert_eq!((stage.index, stage.flags, stage.log_entries.len()), (10, 1, 2));
+        assert_eq!(functions(&stage.log_entries[0].conditions), [2]);
+        assert_eq!(stage.log_entries[0].text, Some(LString::Inline("First".into())));
+        assert_eq!(stage.log_entries[0].next_quest, Some(0x900));
+        assert_eq!(stage.log_entries[1].text, Some(LString::Inline("Second".into())));
+        assert!(quest.stages[1].log_entries.is_empty());
+
+        let objective = &quest.objectives[0];
+        assert_eq!((objective.index, objective.flags), (5, 1));
+        assert_eq!(objective.text, Some(LString::Inline("Go".into())));
+        assert_eq!(functions(&objective.conditions), [3]);
+        assert_eq!((objective.targets[0].target, objective.targets[0].flags), (2, 4));
+        assert_eq!(functions(&objective.targets[0].conditions), [4, 5]);
+
+        assert_eq!(quest.aliases.len(), 2);
+        let alias = &quest.aliases[0];
+        assert_eq!((alias.kind, alias.id, alias.flags), (QuestAliasKind::Reference, 2, 8));
+        assert_eq!(alias.name.as_deref(), Some("Boss"));
+        assert_eq!(functions(&alias.conditions), [6]);
+        assert_eq!(quest.aliases[1].kind, QuestAliasKind::Location);
+        Ok(())
+    }
+
+    #[test]
+    fn detect_game() {
+        let tes4 = |version: f32, form_version: u16| {
+            let mut bytes = b"TES4".to_vec();
+            bytes.extend([0; 16]);
+            bytes[20..22].copy_from_slice(&form_version.to_le_bytes());
+            bytes.extend([0; 2]);
+            bytes.extend(b"HEDR");
+            bytes.extend(12u16.to_le_bytes());
+            bytes.extend(version.to_le_bytes());
+            bytes.extend([0; 8]);
+            bytes
+        };
+        for (version, form_version, game) in [
+            (0.94, 15, Game::Fallout3), (0.94, 43, Game::SkyrimLE), (1.32, 15, Game::FalloutNV), (1.34, 15, Game::FalloutNV),
+            (1.7, 44, Game::SkyrimSE), (1.71, 44, Game::SkyrimSE), (0.95, 131, Game::Fallout4), (0.96, 555, Game::Starfield),
+            // unknown HEDR versions fall back on the form version
+            (2.0, 0, Game::Fallout3), (2.0, 40, Game::SkyrimLE), (2.0, 100, Game::SkyrimSE), (2.0, 200, Game::Fallout4), (2.0, 600, Game::Starfield)
+        ] {
+            let bytes = tes4(version, form_version);
+            assert_eq!(bytes.len(), Game::DETECT_SIZE);
+            assert_eq!(Game::detect(&bytes), Some(game), "{} {}", version, form_version);
+        }
+        for game in [Game::Oblivion, Game::Fallout3, Game::FalloutNV, Game::SkyrimLE, Game::SkyrimSE, Game::Fallout4, Game::Starfield] {
+            let mut bytes = tes4(game.hedr_version(), game.form_version());
+            if game == Game::Oblivion { bytes.drain(16..20); }
+            assert_eq!(Game::detect(&bytes), Some(game));
+            assert_eq!(ESMParser2::slice(&bytes).detect_game().ok(), Some(game));
+        }
+
+        let bytes = tes4(0.94, 15);
+        assert_eq!(Game::detect(&bytes[..20]), None);
+        assert_eq!(Game::detect(b"TES3"), None);
+        assert_eq!(Game::detect(&[b"GRUP".as_slice(), &bytes[4..]].concat()), None);
+    }
+
+    #[test]
+    fn tes3() -> super::Result<()> {
+        let record = |type_id: &[u8; 4], flags: u32, fields: &[(&[u8; 4], &[u8])]| {
+            let mut data = Vec::new();
+            for (type_id, field) in fields {
+                data.extend(*type_id);
+                data.extend((field.len() as u32).to_le_bytes());
+                data.extend(*field);
+            }
+            let mut bytes = type_id.to_vec();
+            bytes.extend((data.len() as u32).to_le_bytes());
+            bytes.extend([0; 4]);
+            bytes.extend(flags.to_le_bytes());
+            bytes.extend(data);
+            bytes
+        };
+        let mut hedr = vec![0; 300];
+        hedr[0..4].copy_from_slice(&1.3f32.to_le_bytes());
+        hedr[4..8].copy_from_slice(&1u32.to_le_bytes());
+        hedr[8..10].copy_from_slice(b"Me");
+        hedr[40..44].copy_from_slice(b"Test");
+        // far more records than the file holds
+        hedr[296..300].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let mut data = record(b"TES3", 0, &[(b"HEDR", &hedr), (b"MAST", b"Morrowind.esm\0"), (b"DATA", &79_837_557u64.to_le_bytes())]);
+        data.extend(record(b"MISC", 0x400, &[(b"NAME", b"gear\0"), (b"MODL", b"gear.nif\0")]));
+        data.extend(record(b"GMST", 0, &[]));
+
+        let mut esm = TES3Parser::new(std::io::Cursor::new(data.as_slice()));
+        let (header, records) = esm.parse_top_level()?;
+        assert_eq!(header.version, 1.3);
+        assert!(header.flags.is_master());
+        assert_eq!((header.author.as_deref(), header.description.as_deref()), (Some("Me"), Some("Test")));
+        assert_eq!(header.masters[0].name, "Morrowind.esm");
+        assert_eq!(header.masters[0].size, 79_837_557);
+        assert_eq!(records.len(), 2);
+        assert!(records[0].header.type_id == b"MISC");
+        assert_eq!(records[0].header.flags, 0x400);
+        assert_eq!(records[0].field(b"MODL").unwrap().zstring(), "gear.nif");
+        assert!(records[1].fields.is_empty());
+
+        // a record running past the end of the file
+        data.extend(&record(b"GMST", 0, &[(b"NAME", b"sOk\0")])[..20]);
+        assert!(TES3Parser::new(std::io::Cursor::new(data.as_slice())).parse_top_level().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn master_kinds() {
+        use super::FileFlags;
+        assert_eq!(FileFlags(0x201).master_kind(Game::SkyrimSE), MasterKind::Light);
+        assert_eq!(FileFlags(0x201).master_kind(Game::Fallout3), MasterKind::Full);
+        assert_eq!(FileFlags(0x101).master_kind(Game::Starfield), MasterKind::Light);
+        assert_eq!(FileFlags(0x401).master_kind(Game::Starfield), MasterKind::Medium);
+        assert_eq!(FileFlags(0x201).master_kind(Game::Starfield), MasterKind::Full);
+        assert!(FileFlags(0x201).is_overlay_for(Game::Starfield));
+        assert_eq!(MasterKind::Medium.load_order_form_id(3, 0x01001234), 0xFD031234);
+        assert_eq!(MasterKind::Light.load_order_form_id(0x12, 0x01000801), 0xFE012801);
+    }
+
+    #[test]
+    fn game_settings() {
+        use super::{Field, GameSettingValue};
+        let data = |bytes: &[u8]| Field { type_id: FourCC(*b"DATA"), data: bytes.to_vec().into() };
+        assert_eq!(GameSettingValue::decode("fJumpHeightMin", &data(&76.0f32.to_le_bytes()), false), GameSettingValue::Float(76.0));
+        assert_eq!(GameSettingValue::decode("iLevelUp", &data(&(-2i32).to_le_bytes()), false), GameSettingValue::Int(-2));
+        assert_eq!(GameSettingValue::decode("bAllowLoad", &data(&[1, 0, 0, 0]), false), GameSettingValue::Bool(true));
+        assert_eq!(GameSettingValue::decode("sYes", &data(b"Yes\0"), false), GameSettingValue::String(LString::Inline("Yes".into())));
+        assert_eq!(GameSettingValue::decode("sYes", &data(&9u32.to_le_bytes()), true), GameSettingValue::String(LString::Id(9, super::StringsKind::Strings)));
+    }
+
+    #[test]
+    fn keywords() {
+        use super::{Field, KeywordIndex, Record};
+        let record = |form_id: u32, keywords: &[u32]| {
+            let mut bytes = [0u8; std::mem::size_of::<esm_bindings::fo3::RecordHeader>()];
+            bytes[0..4].copy_from_slice(b"WEAP");
+            bytes[12..16].copy_from_slice(&form_id.to_le_bytes());
+            let mut kwda = Vec::new();
+            for keyword in keywords { kwda.extend(keyword.to_le_bytes()); }
+            Record { header: unsafe { std::mem::transmute(bytes) }, compressed: None, fields: vec![
+                Field { type_id: FourCC(*b"KSIZ"), data: (keywords.len() as u32).to_le_bytes().to_vec().into() },
+                Field { type_id: FourCC(*b"KWDA"), data: kwda.into() }
+            ] }
+        };
+        let records = [record(0x100, &[0x10, 0x11]), record(0x101, &[0x11]), record(0x102, &[])];
+        assert_eq!(records[0].keywords(), vec![0x10, 0x11]);
+
+        let mut index = KeywordIndex::default();
+        for record in &records { index.add(record); }
+        assert_eq!(index.records_with(0x11), &[0x100, 0x101]);
+        assert_eq!(index.records_with(0x10), &[0x100]);
+        assert!(index.records_with(0x12).is_empty());
+    }
+
+    #[test]
+    fn interior_cells() -> super::Result<()> {
+        use super::TopGroup;
+
+        // object IDs 0x800 to 0x80B are 2048 to 2059, so blocks 8 and 9 get two sub-blocks
+        let (mut data, cells) = build(Game::Fallout4, |builder| {
+            let cells = (0..12).map(|_| builder.add_interior_cell(vec![])).collect::<super::Result<Vec<_>>>()?;
+            builder.add_reference(cells[3], b"REFR", false, vec![field(b"NAME", &[0; 4])])?;
+            Ok(cells)
+        })?;
+
+        {
+            let TopGroup::Cell(blocks) = first_group(&data)? else { panic!("CELL group") };
+            assert_eq!(blocks.len(), 10);
+            let block = blocks.iter().find(|block| block.index == 8).unwrap();
+            assert_eq!(block.sub_blocks.iter().map(|sub_block| sub_block.index).collect::<Vec<_>>(), [4, 5]);
+            assert_eq!(block.sub_blocks[1].cells[0].cell.form_id(), 0x80A);
+            let block = blocks.iter().find(|block| block.index == 1).unwrap();
+            let cell = &block.sub_blocks[0].cells[0];
+            assert_eq!((block.sub_blocks[0].index, cell.cell.form_id()), (5, cells[3]));
+            assert_eq!(cell.cell_children.as_ref().unwrap().temporary.as_ref().unwrap().len(), 1);
+        }
+
+        // a sub-block where a block belongs, then a block where a sub-block belongs
+        let block = find_group(&data, 8u32.to_le_bytes(), 2);
+        data[block + 12] = 3;
+        assert_eq!(plugin_error(&data).0, "interior cell block");
+        data[block + 12] = 2;
+        data[find_group(&data, 4u32.to_le_bytes(), 3) + 12] = 2;
+        let (expected, path) = plugin_error(&data);
+        assert_eq!(expected, "interior cell sub-block");
+        assert!(path.contains("Interior Cell Block(8)"));
+        Ok(())
+    }
+
+    #[test]
+    fn exterior_cells() -> super::Result<()> {
+        use super::TopGroup;
+
+        let (mut data, (persistent, north, south, east)) = build(Game::SkyrimSE, |builder| {
+            let world = builder.add_worldspace(vec![field(b"EDID", b"World\0")])?;
+            let persistent = builder.add_persistent_cell(world, vec![])?;
+            builder.add_reference(persistent, b"REFR", true, vec![])?;
+            let north = builder.add_exterior_cell(world, -3, 40, vec![])?;
+            let south = builder.add_exterior_cell(world, -1, 33, vec![])?;
+            let east = builder.add_exterior_cell(world, 40, -1, vec![])?;
+            builder.add_landscape(north, vec![])?;
+            builder.add_reference(north, b"REFR", true, vec![])?;
+            builder.add_reference(north, b"REFR", false, vec![])?;
+            Ok((persistent, north, south, east))
+        })?;
+
+        {
+            let TopGroup::Worldspace(worlds) = first_group(&data)? else { panic!("WRLD group") };
+            let children = worlds[0].world_children.as_ref().unwrap();
+            assert_eq!(children.cell.as_ref().unwrap().cell.form_id(), persistent);
+            let blocks = children.blocks.iter().map(|block| {
+                (block.x, block.y, block.sub_blocks.iter().map(|sub_block| {
+                    (sub_block.x, sub_block.y, sub_block.cells.iter().map(|cell| cell.cell.form_id()).collect::<Vec<_>>())
+                }).collect::<Vec<_>>())
+            }).collect::<Vec<_>>();
+            assert_eq!(blocks, [
+                (-1, 1, vec![(-1, 5, vec![north]), (-1, 4, vec![south])]),
+                (1, -1, vec![(5, -1, vec![east])])
+            ]);
+            let cell_children = children.blocks[0].sub_blocks[0].cells[0].cell_children.as_ref().unwrap();
+            assert_eq!(cell_children.persistant.as_ref().unwrap().len(), 1);
+            assert_eq!(cell_children.temporary.as_ref().unwrap().len(), 2);
+            assert!(children.blocks[0].sub_blocks[1].cells[0].cell_children.is_none());
+        }
+
+        // the label holds Y before X, so block (-1, 1) is [1, 0, 0xFF, 0xFF]
+        let block = find_group(&data, [1, 0, 0xFF, 0xFF], 4);
+        data[block + 12] = 5;
+        assert_eq!(plugin_error(&data).0, "exterior cell block");
+        data[block + 12] = 4;
+        data[find_group(&data, [5, 0, 0xFF, 0xFF], 5) + 12] = 4;
+        let (expected, path) = plugin_error(&data);
+        assert_eq!(expected, "exterior cell sub-block");
+        assert!(path.contains("Exterior Cell Block(-1, 1)"));
+        Ok(())
+    }
+
+    #[test]
+    fn visible_distant_children() -> super::Result<()> {
+        use super::TopGroup;
+
+        let (mut data, (room, distant)) = build(Game::Fallout3, |builder| {
+            let room = builder.add_interior_cell(vec![])?;
+            let distant = builder.add_visible_distant_reference(room, b"REFR", vec![])?;
+            builder.add_reference(room, b"REFR", false, vec![])?;
+            builder.add_reference(room, b"ACHR", true, vec![])?;
+            builder.add_visible_distant_reference(room, b"REFR", vec![])?;
+            Ok((room, distant))
+        })?;
+
+        {
+            let TopGroup::Cell(blocks) = first_group(&data)? else { panic!("CELL group") };
+            let children = blocks[0].sub_blocks[0].cells[0].cell_children.as_ref().unwrap();
+            // persistent first, the others in the order they were added
+            assert_eq!(children.subgroups.iter().map(|header| header.group_type).collect::<Vec<_>>(), [8, 10, 9]);
+            let visible_distant = children.visible_distant.as_ref().unwrap();
+            assert_eq!(visible_distant.len(), 2);
+            assert_eq!(visible_distant[0].form_id(), distant);
+            assert_eq!(children.temporary.as_ref().unwrap().len(), 1);
+            assert!(children.persistant.as_ref().unwrap()[0].header.type_id == b"ACHR");
+        }
+
+        // a second visible distant group
+        data[find_group(&data, room.to_le_bytes(), 9) + 12] = 10;
+        assert_eq!(plugin_error(&data).0, "one group of each kind");
+        Ok(())
+    }
+
+    #[test]
+    fn dialogue_topics() -> super::Result<()> {
+        use super::{GroupChild, TopGroup};
+
+        let (data, (hello, bye, infos)) = build(Game::SkyrimSE, |builder| {
+            let hello = builder.add_record(b"DIAL", vec![field(b"EDID", b"Hello\0")])?;
+            let bye = builder.add_record(b"DIAL", vec![field(b"EDID", b"Bye\0")])?;
+            let infos = (0..3).map(|i| builder.add_topic_info(hello, vec![field(b"NAM1", &[i])])).collect::<super::Result<Vec<_>>>()?;
+            assert!(matches!(builder.add_topic_info(0x01000fff, vec![]), Err(super::Error::Invalid(_))));
+            Ok((hello, bye, infos))
+        })?;
+
+        let TopGroup::Dialogue(dialogs) = first_group(&data)? else { panic!("DIAL group") };
+        assert_eq!(dialogs.iter().map(|dialog| dialog.dialog.form_id()).collect::<Vec<_>>(), [hello, bye]);
+        assert!(dialogs[1].children.is_none());
+        let children = dialogs[0].children.as_ref().unwrap();
+        assert_eq!(children.header.group_type, 7);
+        assert_eq!(super::group_label(&children.header), hello.to_le_bytes());
+        let parsed = children.children.iter().map(|child| match child {
+            GroupChild::Record(info) => (info.form_id(), info.field(b"NAM1").unwrap().data[0]),
+            GroupChild::Group(_) => panic!("INFO record")
+        }).collect::<Vec<_>>();
+        assert_eq!(parsed, infos.iter().copied().zip(0..3).collect::<Vec<_>>());
+        Ok(())
+    }
+
+    #[test]
+    fn oblivion_headers() -> super::Result<()> {
+        use super::TopGroup;
+
+        let (data, (gear, chair)) = build(Game::Oblivion, |builder| {
+            let gear = builder.add_record(b"MISC", vec![field(b"EDID", b"Gear\0")])?;
+            let room = builder.add_interior_cell(vec![field(b"EDID", b"Room\0")])?;
+            let chair = builder.add_reference(room, b"REFR", false, vec![field(b"NAME", &gear.to_le_bytes())])?;
+            Ok((gear, chair))
+        })?;
+
+        // 20 byte headers: the first field follows straight after, and the
+        // MISC group holds one 20 byte header and an 11 byte EDID
+        assert_eq!(&data[20..24], b"HEDR");
+        let misc = find_group(&data, *b"MISC", 0);
+        assert_eq!(data[misc + 4..misc + 8], 51u32.to_le_bytes());
+        assert_eq!(&data[misc + 20..misc + 24], b"MISC");
+
+        let mut esm = ESMParser2::slice(&data);
+        let plugin = esm.parse_plugin()?;
+        assert_eq!(esm.game(), Game::Oblivion);
+        assert_eq!(plugin.records().count(), 3);
+        let Some((_, TopGroup::Cell(blocks))) = plugin.groups.get(1) else { panic!("CELL group") };
+        let cell = &blocks[0].sub_blocks[0].cells[0];
+        assert_eq!(cell.cell.field(b"EDID").unwrap().zstring(), "Room");
+        assert_eq!(cell.cell_children.as_ref().unwrap().temporary.as_ref().unwrap()[0].form_id(), chair);
+
+        // a new parser loading from the index reads 20 byte headers too
+        let index = ESMParser2::slice(&data).scan()?;
+        let record = ESMParser2::slice(&data).load_form(&index, chair)?.unwrap();
+        assert_eq!(record.field(b"NAME").unwrap().u32_at(0), Some(gear));
+        Ok(())
+    }
+
+    /// A plugin for `game` written out after `f` adds its records, with what `f` returned.
+    fn build<T>(game: Game, f: impl FnOnce(&mut PluginBuilder) -> super::Result<T>) -> super::Result<(Vec<u8>, T)> {
+        let mut builder = PluginBuilder::new(game);
+        let value = f(&mut builder)?;
+        Ok((plugin_bytes(&builder.build())?, value))
+    }
+
+    /// First top group of the plugin in `data`.
+    fn first_group(data: &[u8]) -> super::Result<super::TopGroup<'_>> {
+        let plugin = ESMParser2::slice(data).parse_plugin()?;
+        Ok(plugin.groups.into_iter().next().unwrap().1)
+    }
+
+    /// Expected structure and group path of the error parsing the plugin in `data`.
+    fn plugin_error(data: &[u8]) -> (String, String) {
+        match ESMParser2::slice(data).parse_plugin() {
+            Err(super::Error::Unexpected { expected, path, .. }) => (expected, path),
+            _ => panic!("expected unexpected input")
+        }
+    }
+
+    /// Offset of the first group with the given label and type.
+    fn find_group(data: &[u8], label: [u8; 4], group_type: u32) -> usize {
+        (0..data.len() - 16).find(|&i| {
+            &data[i..i + 4] == b"GRUP" && data[i + 8..i + 12] == label && data[i + 12..i + 16] == group_type.to_le_bytes()
+        }).unwrap()
+    }
+
+    /// A field with a copy of `data`.
+    fn field(type_id: &[u8; 4], data: &[u8]) -> super::Field<'static> {
+        super::Field { type_id: FourCC(*type_id), data: data.to_vec().into() }
+    }
+
+    /// A plugin written out by `EsmWriter`, compressing the records flagged
+    /// as compressed.
+    fn plugin_bytes(plugin: &super::Plugin) -> super::Result<Vec<u8>> {
+        let mut writer = EsmWriter::new(std::io::Cursor::new(Vec::new())).recompress(true);
+        writer.write_plugin(plugin)?;
+        Ok(writer.finish()?.into_inner())
+    }
+
+    /// A small Fallout 4 plugin with two MISC records (0x800 and 0x801) and
+    /// an interior cell (0x802).
+    fn test_builder() -> PluginBuilder {
+        let mut builder = PluginBuilder::new(Game::Fallout4);
+        builder.add_record(b"MISC", vec![field(b"EDID", b"Gear\0")]).unwrap();
+        builder.add_record(b"MISC", vec![field(b"EDID", b"Pipe\0")]).unwrap();
+        builder.add_interior_cell(vec![field(b"EDID", b"Room\0")]).unwrap();
+        builder
+    }
+
+    fn test_plugin() -> Vec<u8> {
+        plugin_bytes(&test_builder().build()).unwrap()
+    }
+
+    /// Flag the records of an unhandled top group as compressed.
+    fn compress(plugin: &mut super::Plugin, type_id: &[u8; 4]) {
+        for (_, group) in &mut plugin.groups {
+            let super::TopGroup::Unhandled(records) = group else { continue };
+            for record in records.iter_mut().filter(|record| record.header.type_id == type_id) {
+                record.header.flags |= super::RECORD_FLAG_COMPRESSED;
+            }
+        }
+    }
+
+    #[test]
+    fn scan() -> super::Result<()> {
+        let mut esm = ESMParser2::new(std::io::Cursor::new(test_plugin()));
+        let index = esm.scan()?;
+        assert_eq!(index.len(), 3);
+
+        let entry = *index.get(0x801).unwrap();
+        assert!(entry.type_id == b"MISC");
+        assert_eq!(index.group_path(&entry).len(), 1);
+        let cell = *index.get(0x802).unwrap();
+        assert_eq!(index.group_path(&cell).len(), 3);
+        assert_eq!(index.group_path(&cell)[1].group_type, 2);
+
+        let record = esm.load_form(&index, 0x801)?.unwrap();
+        assert_eq!(record.field(b"EDID").unwrap().zstring(), "Pipe");
+        assert!(esm.load_form(&index, 0x900)?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn zero_copy() -> super::Result<()> {
+        use std::borrow::Cow;
+        let data = test_plugin();
+        let mut esm = ESMParser2::slice(&data);
+        let index = esm.scan()?;
+        let record = esm.load_form(&index, 0x800)?.unwrap();
+        let edid = record.field(b"EDID").unwrap();
+        assert!(matches!(edid.data, Cow::Borrowed(_)));
+        assert_eq!(edid.zstring(), "Gear");
+
+        let mut esm = ESMParser2::new(std::io::Cursor::new(data.clone()));
+        let record = esm.load_form(&index, 0x800)?.unwrap();
+        assert!(matches!(record.field(b"EDID").unwrap().data, Cow::Owned(_)));
+
+        // any other reader, here one the crate knows nothing about
+        use std::io::{Read, Seek};
+        struct Reader(std::io::Cursor<Vec<u8>>);
+        impl std::io::Read for Reader {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.read(buf) }
+        }
+        impl std::io::Seek for Reader {
+            fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> { self.0.seek(pos) }
+        }
+        let plugin = ESMParser2::owned(Reader(std::io::Cursor::new(data.clone()))).parse_plugin()?;
+        assert_eq!(plugin.records().count(), 3);
+        assert!(plugin.records().all(|record| record.fields.iter().all(|field| matches!(field.data, Cow::Owned(_)))));
+        Ok(())
+    }
+
+    #[test]
+    fn parallel() -> super::Result<()> {
+        // many compressed records in one top group
+        let mut builder = test_builder();
+        for i in 0..0x40u32 {
+            builder.add_record(b"NPC_", vec![field(b"EDID", format!("Npc{}\0", i).as_bytes()), field(b"DATA", &[i as u8; 300])])?;
+        }
+        let mut plugin = builder.build();
+        compress(&mut plugin, b"NPC_");
+        let data = plugin_bytes(&plugin)?;
+
+        let sequential = ESMParser2::slice(&data).parse_plugin()?;
+        let parallel = ESMParser2::slice(&data).parse_plugin_threads(4)?;
+        assert_eq!(sequential.groups.len(), parallel.groups.len());
+        let records = |plugin: &super::Plugin| plugin.records()
+            .map(|record| (record.form_id(), record.fields.iter().map(|field| field.data.to_vec()).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        assert_eq!(records(&sequential), records(&parallel));
+        assert_eq!(records(&parallel).len(), 3 + 0x40);
+
+        let esm = ESMParser2::slice(&data);
+        let inflated = esm.inflate_all(&data, 0, 4);
+        assert_eq!(inflated.len(), 0x40);
+        Ok(())
+    }
+
+    #[test]
+    fn visitor() -> super::Result<()> {
+        #[derive(Default)]
+        struct Counter { groups: usize, left: usize, records: usize, fields: usize, max_depth: usize, stop_at: Option<u32> }
+        impl EsmVisitor for Counter {
+            fn enter_group(&mut self, _: &esm_bindings::fo3::GroupHeader, context: &WalkContext) -> Walk {
+                self.groups += 1;
+                self.max_depth = self.max_depth.max(context.depth);
+                assert_eq!(context.path.len(), context.depth + 1);
+                Walk::Continue
+            }
+            fn leave_group(&mut self, _: &esm_bindings::fo3::GroupHeader, _: &WalkContext) -> Walk {
+                self.left += 1;
+                Walk::Continue
+            }
+            fn record(&mut self, header: &esm_bindings::fo3::RecordHeader, _: &WalkContext) -> Walk {
+                self.records += 1;
+                if self.stop_at == Some(super::form_id(header)) { Walk::Stop } else { Walk::Continue }
+            }
+            fn field(&mut self, _: &super::Field, _: &WalkContext) -> Walk {
+                self.fields += 1;
+                Walk::Continue
+            }
+        }
+
+        let data = test_plugin();
+        let mut counter = Counter::default();
+        assert_eq!(ESMParser2::slice(&data).walk(&mut counter)?, Walk::Continue);
+        // the TES4 record has HEDR and CNAM
+        assert_eq!((counter.groups, counter.left, counter.records, counter.fields), (4, 4, 4, 5));
+        assert_eq!(counter.max_depth, 2);
+
+        let mut counter = Counter { stop_at: Some(0x801), ..Default::default() };
+        assert_eq!(ESMParser2::slice(&data).walk(&mut counter)?, Walk::Stop);
+        assert_eq!((counter.records, counter.fields), (3, 3));
+        Ok(())
+    }
+
+    #[test]
+    fn tree_printer() -> super::Result<()> {
+        let data = test_plugin();
+        let mut printer = TreePrinter::new(Vec::new());
+        ESMParser2::slice(&data).walk(&mut printer)?;
+        let tree = String::from_utf8(printer.finish()?).unwrap();
+        assert_eq!(tree.lines().count(), 8);
+        // the CELL record inside a block and sub-block
+        assert!(tree.lines().last().unwrap().starts_with("      "));
+
+        let mut printer = TreePrinter::new(Vec::new()).max_depth(0).show_offsets(true);
+        ESMParser2::slice(&data).walk(&mut printer)?;
+        let tree = String::from_utf8(printer.finish()?).unwrap();
+        assert_eq!(tree.lines().count(), 5);
+        assert!(tree.starts_with("00000000 "));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> super::Result<()> {
+        let mut builder = test_builder();
+        let packed = builder.add_record(b"WEAP", vec![field(b"EDID", b"Packed\0"), field(b"DATA", &[1; 40])])?;
+        let hello = builder.add_record(b"DIAL", vec![field(b"EDID", b"Hello\0")])?;
+        builder.add_topic_info(hello, vec![])?;
+        let world = builder.add_worldspace(vec![field(b"EDID", b"World\0")])?;
+        let persistent = builder.add_persistent_cell(world, vec![])?;
+        builder.add_reference(persistent, b"REFR", true, vec![field(b"NAME", &[0; 4])])?;
+        let outside = builder.add_exterior_cell(world, 0, 0, vec![])?;
+        builder.add_landscape(outside, vec![field(b"VHGT", &[2; 70000])])?;
+        builder.add_reference(outside, b"REFR", true, vec![])?;
+        let mut plugin = builder.build();
+        compress(&mut plugin, b"WEAP");
+        let data = plugin_bytes(&plugin)?;
+
+        // compressed data is only kept when asked for
+        assert!(ESMParser2::slice(&data).parse_plugin()?.records().all(|record| record.compressed.is_none()));
+
+        let mut esm = ESMParser2::slice(&data);
+        esm.keep_compressed(true);
+        let mut plugin = esm.parse_plugin()?;
+        assert!(plugin_bytes(&plugin)? == data);
+
+        // changed compressed records are recompressed or stored
+        let weapons = plugin.groups.iter_mut().find(|(header, _)| &super::group_label(header) == b"WEAP");
+        let Some((_, super::TopGroup::Unhandled(records))) = weapons else { panic!("WEAP group") };
+        records[0].fields[1].data = vec![2; 40].into();
+        for recompress in [false, true] {
+            let mut writer = EsmWriter::new(std::io::Cursor::new(Vec::new())).recompress(recompress);
+            writer.write_plugin(&plugin)?;
+            let written = writer.finish()?.into_inner();
+            let record = ESMParser2::slice(&written).load_form(&ESMParser2::slice(&written).scan()?, packed)?.unwrap();
+            assert_eq!(record.header.flags & super::RECORD_FLAG_COMPRESSED != 0, recompress);
+            assert_eq!(record.fields[1].data.as_ref(), &[2u8; 40]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn builder() -> super::Result<()> {
+        use super::{GameSettingValue, TopGroup};
+
+        for game in [Game::Oblivion, Game::Fallout3, Game::SkyrimSE, Game::Fallout4, Game::Starfield] {
+            let mut builder = PluginBuilder::new(game);
+            builder.add_master("Base.esm", 1234)?;
+            let misc = builder.add_record(b"MISC", vec![field(b"EDID", b"Gear\0")])?;
+            builder.add_record(b"GMST", vec![field(b"EDID", b"iLevels\0"), field(b"DATA", &7i32.to_le_bytes())])?;
+            let room = builder.add_interior_cell(vec![field(b"EDID", b"Room\0")])?;
+            let chair = builder.add_reference(room, b"REFR", false, vec![field(b"NAME", &misc.to_le_bytes())])?;
+            let world = builder.add_worldspace(vec![field(b"EDID", b"World\0")])?;
+            builder.add_persistent_cell(world, vec![])?;
+            let outside = builder.add_exterior_cell(world, -3, 40, vec![])?;
+            builder.add_landscape(outside, vec![field(b"VHGT", &[0; 1096])])?;
+            builder.add_reference(outside, b"REFR", true, vec![field(b"NAME", &misc.to_le_bytes())])?;
+            assert_eq!(misc, 0x01000800);
+            assert!(matches!(builder.add_reference(0x01000fff, b"REFR", false, vec![]), Err(super::Error::Invalid(_))));
+            assert!(matches!(builder.add_master("Late.esm", 0), Err(super::Error::Invalid(_))));
+            let plugin = builder.build();
+            let data = plugin_bytes(&plugin)?;
+
+            let mut esm = ESMParser2::slice(&data);
+            let parsed = esm.parse_plugin()?;
+            assert_eq!(esm.game(), game);
+            assert_eq!(parsed.file_header.masters[0].name, "Base.esm");
+            assert_eq!(parsed.file_header.next_object_id, 0x809);
+            assert_eq!(parsed.records().count(), plugin.records().count());
+            let Some((_, TopGroup::GameSetting(settings))) = parsed.groups.get(1) else { panic!("GMST group") };
+            assert_eq!(settings[0].value, GameSettingValue::Int(7));
+
+            let index = ESMParser2::slice(&data).scan()?;
+            let entry = *index.get(chair).unwrap();
+            assert_eq!(index.group_path(&entry).len(), 5);
+
+            assert!(plugin_bytes(&parsed)? == data);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn index_cache() -> super::Result<()> {
+        use super::{cache_path, FormIdIndex};
+        let path = std::env::temp_dir().join(format!("esm-parser-{}.esp", std::process::id()));
+        std::fs::write(&path, test_plugin())?;
+
+        let index = FormIdIndex::load_or_scan(&path)?;
+        assert_eq!(index.form_id("Pipe"), Some(0x801));
+        assert!(cache_path(&path).exists());
+
+        let cached = FormIdIndex::load_or_scan(&path)?;
+        assert_eq!(cached.len(), index.len());
+        assert_eq!(cached.form_id("Room"), Some(0x802));
+        assert_eq!(cached.get(0x800), index.get(0x800));
+
+        // same size and modification time, different content
+        let mut data = std::fs::read(&path)?;
+        let modified = std::fs::metadata(&path)?.modified()?;
+        let gear = data.windows(5).position(|w| w == b"Gear\0").unwrap();
+        data[gear..gear + 4].copy_from_slice(b"Cogs");
+        std::fs::write(&path, &data)?;
+        std::fs::File::options().write(true).open(&path)?.set_modified(modified)?;
+        let (mut esm, index) = ESMParser2::file_indexed(&path)?;
+        assert_eq!(index.form_id("Cogs"), Some(0x800));
+        assert_eq!(esm.load_form(&index, 0x801)?.unwrap().field(b"EDID").unwrap().zstring(), "Pipe");
+
+        // a corrupt EditorID length is rejected rather than allocated
+        let mut cache = Vec::new();
+        index.write_cache(&super::CacheKey::default(), &mut cache)?;
+        let length = cache.windows(4).position(|w| w == b"Pipe").unwrap() - 4;
+        cache[length..length + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(FormIdIndex::read_cache(&mut cache.as_slice()).is_err());
+
+        std::fs::remove_file(cache_path(&path))?;
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn string_tables() -> super::Result<()> {
+        use super::{StringTable, StringsKind};
+        let mut data = Vec::new();
+        for word in [2u32, 11, 1, 0, 7, 5] { data.extend(word.to_le_bytes()); }
+        data.extend(b"Iron\0Steel\0");
+        let strings = StringTable::parse(&data, StringsKind::Strings)?;
+        assert_eq!(strings.get(1), Some("Iron"));
+        assert_eq!(strings.get(7), Some("Steel"));
+        assert_eq!(strings.get(3), None);
+
+        let mut data = Vec::new();
+        for word in [1u32, 9, 3, 0, 5] { data.extend(word.to_le_bytes()); }
+        data.extend(b"Book\0");
+        let table = StringTable::parse(&data, StringsKind::DLStrings)?;
+        assert_eq!(table.get(3), Some("Book"));
+
+        // the same ID in two tables, picked by the field type
+        let tables = StringTables { strings, dlstrings: table, ..Default::default() };
+        let field = |type_id: &[u8; 4]| super::Field { type_id: FourCC(*type_id), data: 3u32.to_le_bytes().to_vec().into() };
+        assert_eq!(tables.resolve(&field(b"FULL").lstring(true)), None);
+        assert_eq!(tables.resolve(&field(b"DESC").lstring(true)), Some("Book"));
+        assert_eq!(field(b"NAM1").lstring(true), LString::Id(3, StringsKind::ILStrings));
+        Ok(())
     }
 }
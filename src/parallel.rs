@@ -0,0 +1,131 @@
+//! Parallel parsing of top groups from in-memory data.
+
+use crate::*;
+
+use std::collections::HashMap;
+use std::io::{Cursor, Seek};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+//------------------------------------------------------------------------------
+
+impl<'a> ESMParser2<Cursor<&'a [u8]>> {
+    /// Parse the whole plugin like `parse_plugin`, spreading the top groups
+    /// over a pool of threads.
+    ///
+    /// A scan of the record headers finds the top groups and the compressed
+    /// records. The compressed records are inflated first, spread over all
+    /// threads whichever group they are in, so one large CELL, WRLD or NPC_
+    /// group does not leave the other threads idle. Each top group is then
+    /// parsed by whichever thread is free, largest first. The result is the
+    /// same as parsing sequentially.
+    pub fn parse_plugin_parallel(&mut self) -> Result<Plugin<'a>> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        self.parse_plugin_threads(threads)
+    }
+
+    /// Parse the whole plugin using up to `threads` threads.
+    pub fn parse_plugin_threads(&mut self, threads: usize) -> Result<Plugin<'a>> {
+        let data: &'a [u8] = *self.reader().get_ref();
+        let total_size = data.len() as u64;
+        let (header, file_header) = self.parse_tes4()?;
+
+        // top group positions, from their headers only
+        let mut offsets = Vec::new();
+        let mut offset = self.reader().stream_position()?;
+        while offset < total_size {
+            let group_header = self.read_group_header()?;
+            offsets.push((offset, group_header));
+            offset = self.group_end(&group_header)?;
+            self.reader().seek(std::io::SeekFrom::Start(offset))?;
+        }
+
+        // hand each top group the records inflated inside it, so parsing
+        // threads never share a map
+        let mut inflated: Vec<HashMap<u64, Vec<u8>>> = offsets.iter().map(|_| HashMap::new()).collect();
+        for (offset, record) in self.inflate_all(data, offsets.first().map_or(total_size, |(offset, _)| *offset), threads) {
+            let i = offsets.partition_point(|&(start, _)| start < offset) - 1;
+            inflated[i].insert(offset, record);
+        }
+        let inflated: Vec<Mutex<Option<HashMap<u64, Vec<u8>>>>> = inflated.into_iter().map(|map| Mutex::new(Some(map))).collect();
+
+        let mut order: Vec<usize> = (0..offsets.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(offsets[i].1.size));
+
+        let results: Vec<Mutex<Option<Result<TopGroup<'a>>>>> = offsets.iter().map(|_| Mutex::new(None)).collect();
+        let next = AtomicUsize::new(0);
+        let (game, localised, keep_compressed, strings) = (self.game, self.localised, self.keep_compressed, self.strings.clone());
+
+        std::thread::scope(|scope| {
+            for _ in 0..threads.clamp(1, offsets.len().max(1)) {
+                scope.spawn(|| {
+                    let mut parser = ESMParser2::slice(data);
+                    parser.game = game;
+                    parser.localised = localised;
+                    parser.keep_compressed = keep_compressed;
+                    parser.strings = strings.clone();
+                    while let Some(&i) = order.get(next.fetch_add(1, Ordering::Relaxed)) {
+                        parser.inflated = inflated[i].lock().unwrap().take();
+                        let result = parser.reader().seek(std::io::SeekFrom::Start(offsets[i].0))
+                            .map_err(Error::from)
+                            .and_then(|_| parser.parse_top_group());
+                        *results[i].lock().unwrap() = Some(result);
+                    }
+                });
+            }
+        });
+
+        // the first error in file order, as a sequential parse would report
+        let mut groups = Vec::with_capacity(offsets.len());
+        for ((_, group_header), result) in offsets.into_iter().zip(results) {
+            let group = result.into_inner().unwrap().expect("every top group is parsed")?;
+            groups.push((group_header, group));
+        }
+        self.reader().seek(std::io::SeekFrom::Start(total_size))?;
+        Ok(Plugin { header, file_header, groups })
+    }
+
+    /// Inflate every compressed record from `start` to the end of `data` on
+    /// up to `threads` threads, keyed by the offset of the record data.
+    ///
+    /// Records that fail to inflate are left out, so parsing inflates them
+    /// again and reports the error where it happens.
+    pub(crate) fn inflate_all(&self, data: &[u8], start: u64, threads: usize) -> HashMap<u64, Vec<u8>> {
+        let header_size = self.game.header_size() as usize;
+        let mut compressed = Vec::new();
+        let mut pos = start as usize;
+        while let Some(header) = data.get(pos..pos + header_size) {
+            let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+            if &header[0..4] == b"GRUP" {
+                // step into the group, its children follow the header
+                pos += header_size;
+                continue
+            }
+            let flags = u32::from_le_bytes([header[8], header[9], header[10], header[11]]);
+            let offset = pos + header_size;
+            if flags & RECORD_FLAG_COMPRESSED != 0 && offset + size <= data.len() {
+                compressed.push((offset, size));
+            }
+            pos = offset + size;
+        }
+
+        // each thread keeps its own results until the scope joins
+        let next = AtomicUsize::new(0);
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.clamp(1, compressed.len().max(1))).map(|_| scope.spawn(|| {
+                let mut done = Vec::new();
+                while let Some(&(offset, size)) = compressed.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    if let Ok(record) = inflate_record(&data[offset..offset + size]) {
+                        done.push((offset as u64, record));
+                    }
+                }
+                done
+            })).collect();
+            let mut inflated = HashMap::with_capacity(compressed.len());
+            for worker in workers {
+                inflated.extend(worker.join().expect("inflating thread panicked"));
+            }
+            inflated
+        })
+    }
+}
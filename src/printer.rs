@@ -0,0 +1,84 @@
+//! Indented tree dump of a plugin.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+/// An `EsmVisitor` that writes an indented tree of group and record headers.
+///
+/// ```ignore
+/// let mut printer = TreePrinter::new(std::io::stdout().lock()).max_depth(2).hide_refs(true);
+/// parser.walk(&mut printer)?;
+/// printer.finish()?;
+/// ```
+pub struct TreePrinter<W: Write> {
+    out: W,
+    max_depth: Option<usize>,
+    hide_refs: bool,
+    show_offsets: bool,
+    error: Option<std::io::Error>
+}
+
+impl<W: Write> TreePrinter<W> {
+    pub fn new(out: W) -> Self {
+        TreePrinter { out, max_depth: None, hide_refs: false, show_offsets: false, error: None }
+    }
+
+    /// Skip groups nested deeper than `depth`, where top groups are at depth 0.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Leave out placed references (REFR), which make up most of a worldspace.
+    pub fn hide_refs(mut self, hide: bool) -> Self {
+        self.hide_refs = hide;
+        self
+    }
+
+    /// Start each line with the stream position of the header.
+    pub fn show_offsets(mut self, show: bool) -> Self {
+        self.show_offsets = show;
+        self
+    }
+
+    /// The writer, or the first error writing to it.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => { self.out.flush()?; Ok(self.out) }
+        }
+    }
+
+    fn line(&mut self, context: &WalkContext, text: std::fmt::Arguments) -> Walk {
+        let result = if self.show_offsets {
+            writeln!(self.out, "{:08x} {:indent$}{}", context.offset, "", text, indent = context.depth * 2)
+        } else {
+            writeln!(self.out, "{:indent$}{}", "", text, indent = context.depth * 2)
+        };
+        match result {
+            Ok(()) => Walk::Continue,
+            Err(e) => { self.error = Some(e); Walk::Stop }
+        }
+    }
+}
+
+impl<W: Write> EsmVisitor for TreePrinter<W> {
+    fn enter_group(&mut self, header: &GroupHeader, context: &WalkContext) -> Walk {
+        if self.max_depth.is_some_and(|max| context.depth > max) { return Walk::Skip }
+        if context.depth == 0 {
+            self.line(context, format_args!("{:?} --------------------------------------------------", header.get_label()))
+        } else {
+            self.line(context, format_args!("{:?}", header.get_label()))
+        }
+    }
+
+    fn record(&mut self, header: &RecordHeader, context: &WalkContext) -> Walk {
+        if self.hide_refs && header.type_id == b"REFR" { return Walk::Skip }
+        match self.line(context, format_args!("{:?}", header)) {
+            // fields are not printed, so don't read them
+            Walk::Continue => Walk::Skip,
+            walk => walk
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Quest (QUST) record decoding.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+/// A CTDA condition, kept as raw data since its layout differs between games.
+#[derive(Debug, Clone)]
+pub struct Condition {
+    pub data: Vec<u8>
+}
+
+impl Condition {
+    fn field(&self) -> Field<'_> {
+        Field { type_id: FourCC(*b"CTDA"), data: Cow::Borrowed(&self.data) }
+    }
+
+    /// Comparison operator, the top three bits of the first byte.
+    pub fn operator(&self) -> u8 { self.data.first().map_or(0, |b| b >> 5) }
+
+    /// Flags (OR, use global, ...), the low five bits of the first byte.
+    pub fn flags(&self) -> u8 { self.data.first().map_or(0, |b| b & 0x1f) }
+
+    /// Comparison value, or the form ID of a global when the flags say so.
+    pub fn comparison_value(&self) -> Option<f32> { self.field().f32_at(4) }
+
+    /// Condition function index.
+    pub fn function(&self) -> Option<u16> { self.field().u16_at(8) }
+
+    pub fn parameter1(&self) -> Option<u32> { self.field().u32_at(12) }
+
+    pub fn parameter2(&self) -> Option<u32> { self.field().u32_at(16) }
+}
+
+//------------------------------------------------------------------------------
+
+/// Stage started by an INDX field.
+#[derive(Debug, Clone, Default)]
+pub struct QuestStage {
+    pub index: u16,
+    pub flags: u8,
+    pub log_entries: Vec<QuestLogEntry>
+}
+
+/// Log entry started by a QSDT field.
+#[derive(Debug, Clone, Default)]
+pub struct QuestLogEntry {
+    pub flags: u8,
+    pub conditions: Vec<Condition>,
+    /// CNAM
+    pub text: Option<LString>,
+    /// NAM0
+    pub next_quest: Option<u32>
+}
+
+/// Objective started by a QOBJ field.
+#[derive(Debug, Clone, Default)]
+pub struct QuestObjective {
+    pub index: u16,
+    /// FNAM
+    pub flags: u32,
+    /// NNAM
+    pub text: Option<LString>,
+    /// Conditions before the first target.
+    pub conditions: Vec<Condition>,
+    pub targets: Vec<QuestTarget>
+}
+
+/// Objective target, a QSTA field and the conditions that follow it.
+#[derive(Debug, Clone, Default)]
+pub struct QuestTarget {
+    /// Target alias, or target reference in games without aliases.
+    pub target: u32,
+    pub flags: u32,
+    pub conditions: Vec<Condition>
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuestAliasKind {
+    /// ALST
+    Reference,
+    /// ALLS
+    Location
+}
+
+/// Alias, from ALST or ALLS up to ALED.
+#[derive(Debug, Clone)]
+pub struct QuestAlias {
+    pub kind: QuestAliasKind,
+    pub id: u32,
+    /// ALID
+    pub name: Option<String>,
+    /// FNAM
+    pub flags: u32,
+    pub conditions: Vec<Condition>
+}
+
+/// Decoded QUST fields.
+#[derive(Debug, Clone, Default)]
+pub struct QuestData {
+    pub editor_id: Option<String>,
+    pub name: Option<LString>,
+    /// Conditions on the quest itself.
+    pub conditions: Vec<Condition>,
+    pub stages: Vec<QuestStage>,
+    pub objectives: Vec<QuestObjective>,
+    pub aliases: Vec<QuestAlias>
+}
+
+impl QuestData {
+    /// Decode the fields of a QUST record.
+    ///
+    /// Fields are read in order, with stage, objective and alias fields
+    /// attached to the most recent INDX, QOBJ or ALST/ALLS.
+    pub fn decode(record: &Record, localised: bool) -> QuestData {
+        #[derive(PartialEq)]
+        enum Context { Quest, Stage, Objective, Alias }
+
+        let mut quest = QuestData::default();
+        let mut context = Context::Quest;
+
+        for field in &record.fields {
+            match &field.type_id.0 {
+                b"EDID" => { quest.editor_id = Some(field.zstring()); },
+                b"FULL" => { quest.name = Some(field.lstring(localised)); },
+                b"INDX" => {
+                    context = Context::Stage;
+                    quest.stages.push(QuestStage {
+                        index: field.u16_at(0).unwrap_or(0),
+                        flags: field.data.get(2).copied().unwrap_or(0),
+                        log_entries: Vec::new()
+                    });
+                },
+                b"QSDT" if context == Context::Stage => {
+                    let flags = field.data.first().copied().unwrap_or(0);
+                    if let Some(stage) = quest.stages.last_mut() {
+                        stage.log_entries.push(QuestLogEntry { flags, ..Default::default() });
+                    }
+                },
+                b"CNAM" if context == Context::Stage => {
+                    if let Some(entry) = last_log_entry(&mut quest) { entry.text = Some(field.lstring(localised)); }
+                },
+                b"NAM0" if context == Context::Stage => {
+                    if let Some(entry) = last_log_entry(&mut quest) { entry.next_quest = field.u32_at(0); }
+                },
+                b"QOBJ" => {
+                    context = Context::Objective;
+                    quest.objectives.push(QuestObjective { index: field.u16_at(0).unwrap_or(0), ..Default::default() });
+                },
+                b"NNAM" if context == Context::Objective => {
+                    if let Some(objective) = quest.objectives.last_mut() { objective.text = Some(field.lstring(localised)); }
+                },
+                b"QSTA" if context == Context::Objective => {
+                    if let Some(objective) = quest.objectives.last_mut() {
+                        objective.targets.push(QuestTarget {
+                            target: field.u32_at(0).unwrap_or(0),
+                            flags: field.u32_at(4).unwrap_or(0),
+                            conditions: Vec::new()
+                        });
+                    }
+                },
+                b"ALST" | b"ALLS" => {
+                    context = Context::Alias;
+                    let kind = if field.type_id == b"ALST" { QuestAliasKind::Reference } else { QuestAliasKind::Location };
+                    quest.aliases.push(QuestAlias {
+                        kind, id: field.u32_at(0).unwrap_or(0), name: None, flags: 0, conditions: Vec::new()
+                    });
+                },
+                b"ALID" if context == Context::Alias => {
+                    if let Some(alias) = quest.aliases.last_mut() { alias.name = Some(field.zstring()); }
+                },
+                b"ALED" => { context = Context::Quest; },
+                b"FNAM" => {
+                    let flags = field.u32_at(0).unwrap_or(0);
+                    match context {
+                        Context::Objective => if let Some(o) = quest.objectives.last_mut() { o.flags = flags; },
+                        Context::Alias => if let Some(a) = quest.aliases.last_mut() { a.flags = flags; },
+                        _ => {}
+                    }
+                },
+                b"CTDA" => {
+                    let condition = Condition { data: field.data.to_vec() };
+                    let conditions = match context {
+                        Context::Stage => last_log_entry(&mut quest).map(|entry| &mut entry.conditions),
+                        Context::Objective => quest.objectives.last_mut().map(|objective| match objective.targets.last_mut() {
+                            Some(target) => &mut target.conditions,
+                            None => &mut objective.conditions
+                        }),
+                        Context::Alias => quest.aliases.last_mut().map(|alias| &mut alias.conditions),
+                        Context::Quest => None
+                    };
+                    match conditions {
+                        Some(conditions) => conditions.push(condition),
+                        None => quest.conditions.push(condition)
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        quest
+    }
+}
+
+/// Log entry that stage fields apply to, created if the stage has none yet.
+fn last_log_entry(quest: &mut QuestData) -> Option<&mut QuestLogEntry> {
+    let stage = quest.stages.last_mut()?;
+    if stage.log_entries.is_empty() { stage.log_entries.push(QuestLogEntry::default()); }
+    stage.log_entries.last_mut()
+}
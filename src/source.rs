@@ -0,0 +1,105 @@
+//! Readers that records can be parsed from, borrowing from in-memory data where possible.
+
+use crate::*;
+
+use std::fs::File;
+use std::io::{BufReader, Cursor, Seek};
+
+//------------------------------------------------------------------------------
+
+/// A reader that hands out record data, borrowed for the lifetime `'a` when
+/// the whole plugin is already in memory.
+///
+/// Parsing from a `Cursor<&'a [u8]>`, such as a memory map or `include_bytes!`
+/// data, gives records whose uncompressed fields borrow from the data instead
+/// of copying it. Other readers give records that own their fields, and any
+/// `Read + Seek` reader can be parsed by wrapping it in `Owned`.
+pub trait Source<'a>: Read + Seek {
+    /// Read the next `size` bytes.
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>>;
+}
+
+/// Read into a new buffer, for readers that cannot lend their data.
+///
+/// The buffer grows with what is actually read, so a corrupt size fails at
+/// the end of the input instead of allocating the whole size up front.
+fn read_owned(reader: &mut impl Read, size: usize) -> std::io::Result<Cow<'static, [u8]>> {
+    let mut data = Vec::new();
+    reader.take(size as u64).read_to_end(&mut data)?;
+    if data.len() != size { return Err(std::io::ErrorKind::UnexpectedEof.into()) }
+    Ok(Cow::Owned(data))
+}
+
+impl<'a> Source<'a> for Cursor<&'a [u8]> {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> {
+        let data: &'a [u8] = *self.get_ref();
+        let start = (self.position() as usize).min(data.len());
+        let bytes = data.get(start..start + size).ok_or(std::io::ErrorKind::UnexpectedEof)?;
+        self.set_position((start + size) as u64);
+        Ok(Cow::Borrowed(bytes))
+    }
+}
+
+impl<'a> Source<'a> for Cursor<Vec<u8>> {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> { read_owned(self, size) }
+}
+
+impl<'a> Source<'a> for File {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> { read_owned(self, size) }
+}
+
+impl<'a, R: Read + Seek> Source<'a> for BufReader<R> {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> { read_owned(self, size) }
+}
+
+impl<'a, S: Source<'a> + ?Sized> Source<'a> for &mut S {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> { (**self).read_bytes(size) }
+}
+
+impl<'a, S: Source<'a> + ?Sized> Source<'a> for Box<S> {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> { (**self).read_bytes(size) }
+}
+
+/// Any `Read + Seek` reader as a `Source`, always copying record data.
+#[derive(Debug)]
+pub struct Owned<R>(pub R);
+
+impl<R: Read> Read for Owned<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> { self.0.read(buf) }
+}
+
+impl<R: Seek> Seek for Owned<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> { self.0.seek(pos) }
+    fn stream_position(&mut self) -> std::io::Result<u64> { self.0.stream_position() }
+    fn seek_relative(&mut self, offset: i64) -> std::io::Result<()> { self.0.seek_relative(offset) }
+}
+
+impl<'a, R: Read + Seek> Source<'a> for Owned<R> {
+    fn read_bytes(&mut self, size: usize) -> std::io::Result<Cow<'a, [u8]>> { read_owned(self, size) }
+}
+
+//------------------------------------------------------------------------------
+
+impl<'a> ESMParser2<Cursor<&'a [u8]>> {
+    /// Parse plugin data that is already in memory, borrowing field data from it.
+    pub fn slice(data: &'a [u8]) -> Self {
+        ESMParser2::new(Cursor::new(data))
+    }
+}
+
+impl<R: Read + Seek> ESMParser2<Owned<R>> {
+    /// Parse from any reader, such as a network stream or a decrypting
+    /// wrapper, with records that own their fields.
+    pub fn owned(reader: R) -> Self {
+        ESMParser2::new(Owned(reader))
+    }
+}
+
+/// Memory map a plugin, to be parsed with `ESMParser2::slice`.
+///
+/// The map must not be modified by other processes while it is parsed.
+#[cfg(feature = "mmap")]
+pub fn map_file(path: impl AsRef<std::path::Path>) -> Result<memmap2::Mmap> {
+    let file = File::open(path)?;
+    Ok(unsafe { memmap2::Mmap::map(&file)? })
+}
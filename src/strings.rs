@@ -0,0 +1,170 @@
+//! Localised string tables (.STRINGS, .DLSTRINGS and .ILSTRINGS).
+
+use crate::*;
+
+use std::collections::HashMap;
+use std::path::Path;
+
+//------------------------------------------------------------------------------
+
+/// String table file format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringsKind {
+    /// Null terminated entries, used by most fields.
+    Strings,
+    /// Length prefixed entries, used by descriptions and book text.
+    DLStrings,
+    /// Length prefixed entries, used by dialogue.
+    ILStrings
+}
+
+impl StringsKind {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            StringsKind::Strings => "STRINGS",
+            StringsKind::DLStrings => "DLSTRINGS",
+            StringsKind::ILStrings => "ILSTRINGS"
+        }
+    }
+
+    /// Table a localised string field is stored in, by field type.
+    pub fn of_field(type_id: &FourCC) -> StringsKind {
+        match &type_id.0 {
+            // descriptions, book text and quest log entries
+            b"DESC" | b"CNAM" => StringsKind::DLStrings,
+            // dialogue responses
+            b"NAM1" => StringsKind::ILStrings,
+            _ => StringsKind::Strings
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+
+/// Decode text as UTF-8, falling back to Windows-1252.
+pub fn decode_string(bytes: &[u8]) -> String {
+    if let Ok(s) = std::str::from_utf8(bytes) { return s.to_owned() }
+    const CP1252: [char; 32] = [
+        '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+        '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ'
+    ];
+    bytes.iter().map(|&b| match b {
+        0x80..=0x9f => CP1252[(b - 0x80) as usize],
+        _ => b as char
+    }).collect()
+}
+
+//------------------------------------------------------------------------------
+
+/// A single string table file.
+#[derive(Debug, Default)]
+pub struct StringTable {
+    strings: HashMap<u32, String>
+}
+
+impl StringTable {
+    /// Parse the contents of a string table file.
+    pub fn parse(data: &[u8], kind: StringsKind) -> Result<StringTable> {
+        let truncated = |pos: usize| Error::unexpected(pos as u64, "string table data", "end of file");
+        let read_u32 = |pos: usize| data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .ok_or_else(|| truncated(pos));
+
+        let count = read_u32(0)? as usize;
+        let data_size = read_u32(4)? as usize;
+        let data_start = 8 + count * 8;
+        let blob = data.get(data_start..data_start + data_size).ok_or_else(|| truncated(data_start))?;
+
+        let mut strings = HashMap::with_capacity(count);
+        for i in 0..count {
+            let id = read_u32(8 + i * 8)?;
+            let offset = read_u32(12 + i * 8)? as usize;
+            let entry = blob.get(offset..).ok_or_else(|| truncated(data_start + offset))?;
+            let text = match kind {
+                StringsKind::Strings => entry,
+                // length includes the null terminator
+                StringsKind::DLStrings | StringsKind::ILStrings => {
+                    let length = read_u32(data_start + offset)? as usize;
+                    entry.get(4..4 + length).ok_or_else(|| truncated(data_start + offset + 4))?
+                }
+            };
+            let end = text.iter().position(|&c| c == 0).unwrap_or(text.len());
+            strings.insert(id, decode_string(&text[..end]));
+        }
+
+        Ok(StringTable { strings })
+    }
+
+    pub fn get(&self, id: u32) -> Option<&str> {
+        self.strings.get(&id).map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize { self.strings.len() }
+
+    pub fn is_empty(&self) -> bool { self.strings.is_empty() }
+}
+
+//------------------------------------------------------------------------------
+
+/// The three string tables belonging to a localised plugin in one language.
+#[derive(Debug, Default)]
+pub struct StringTables {
+    pub strings: StringTable,
+    pub dlstrings: StringTable,
+    pub ilstrings: StringTable
+}
+
+impl StringTables {
+    /// Load `<plugin>_<language>.*STRINGS` from a strings directory, e.g.
+    /// `StringTables::load("Data/Strings", "Skyrim.esm", "english")`.
+    ///
+    /// Missing files are treated as empty tables.
+    pub fn load(dir: impl AsRef<Path>, plugin: &str, language: &str) -> Result<StringTables> {
+        let stem = Path::new(plugin).file_stem().and_then(|s| s.to_str()).unwrap_or(plugin);
+        let load = |kind: StringsKind| -> Result<StringTable> {
+            let path = dir.as_ref().join(format!("{}_{}.{}", stem, language, kind.extension()));
+            match std::fs::read(path) {
+                Ok(data) => StringTable::parse(&data, kind),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(StringTable::default()),
+                Err(e) => Err(e.into())
+            }
+        };
+        Ok(StringTables {
+            strings: load(StringsKind::Strings)?,
+            dlstrings: load(StringsKind::DLStrings)?,
+            ilstrings: load(StringsKind::ILStrings)?
+        })
+    }
+
+    pub fn table(&self, kind: StringsKind) -> &StringTable {
+        match kind {
+            StringsKind::Strings => &self.strings,
+            StringsKind::DLStrings => &self.dlstrings,
+            StringsKind::ILStrings => &self.ilstrings
+        }
+    }
+
+    /// Look up a string ID in the table of the given kind, IDs are only unique per table.
+    pub fn get(&self, kind: StringsKind, id: u32) -> Option<&str> {
+        self.table(kind).get(id)
+    }
+
+    /// Text of a potentially localised string.
+    pub fn resolve<'s>(&'s self, lstring: &'s LString) -> Option<&'s str> {
+        match lstring {
+            LString::Id(id, kind) => self.get(*kind, *id),
+            LString::Inline(text) => Some(text)
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+
+/// A potentially localised string field.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LString {
+    /// String table ID and the table it is in, used when the plugin is localised.
+    Id(u32, StringsKind),
+    /// Text stored in the field itself.
+    Inline(String)
+}
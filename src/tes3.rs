@@ -0,0 +1,109 @@
+//! Morrowind (TES3) plugin reader.
+//!
+//! TES3 plugins are a flat stream of records with 16 byte headers and no
+//! groups. Records are returned as the same `Record` and `Field` types used by
+//! `ESMParser2`, with the header mapped onto the later layout.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+#[chunk_parser(custom)]
+pub struct TES3Parser {
+    /// Number of records announced by the TES3 HEDR field.
+    num_records: u32
+}
+
+impl<R> TES3Parser<R> where R: std::io::Read + std::io::Seek {
+    /// Parse the TES3 header record and the records that follow it.
+    pub fn parse_top_level(&mut self) -> Result<(FileHeader, Vec<Record<'static>>)> {
+        let total_size = self.reader().seek(std::io::SeekFrom::End(0))?;
+        let header = self.parse_file_header()?;
+        // the HEDR count is a hint, capped by the 16 byte minimum record size
+        let mut records = Vec::with_capacity((self.num_records as usize).min(total_size as usize / 16));
+        while self.reader().stream_position()? < total_size {
+            records.push(self.parse_record()?);
+        }
+        Ok((header, records))
+    }
+
+    /// Parse the TES3 record at the start of the file.
+    pub fn parse_file_header(&mut self) -> Result<FileHeader> {
+        self.reader().seek(std::io::SeekFrom::Start(0))?;
+        let record = self.parse_record()?;
+        if record.header.type_id != b"TES3" {
+            return Err(Error::unexpected(0, "TES3 record", record.header.type_id.to_string()))
+        }
+
+        let mut header = FileHeader::default();
+        for field in &record.fields {
+            match &field.type_id.0 {
+                b"HEDR" => {
+                    // version, flags, 32 byte author, 256 byte description, number of records
+                    if field.data.len() < 300 {
+                        return Err(Error::unexpected(0, "300 byte HEDR field", format!("{} bytes", field.data.len())))
+                    }
+                    header.version = field.f32_at(0).unwrap_or(0.0);
+                    header.flags = FileFlags(field.u32_at(4).unwrap_or(0) & 0x00000001);
+                    header.author = Some(fixed_string(&field.data[8..40]));
+                    header.description = Some(fixed_string(&field.data[40..296]));
+                    header.num_records = field.u32_at(296).unwrap_or(0);
+                },
+                b"MAST" => { header.masters.push(Master { name: field.zstring(), size: 0 }); },
+                b"DATA" => {
+                    if let (Some(master), Some(size)) = (header.masters.last_mut(), field.u64_at(0)) { master.size = size; }
+                },
+                _ => {}
+            }
+        }
+
+        self.num_records = header.num_records;
+        Ok(header)
+    }
+
+    /// Parse the next record.
+    ///
+    /// The TES3 header (type, size, unknown, flags) is stored in a `RecordHeader`
+    /// as type, size and flags, with the unknown value in the version control
+    /// field and a form ID of zero.
+    pub fn parse_record(&mut self) -> Result<Record<'static>> {
+        let start = self.reader().stream_position()?;
+        let raw: [u8; 16] = self.read()?;
+        let mut bytes = [0u8; std::mem::size_of::<RecordHeader>()];
+        bytes[0..8].copy_from_slice(&raw[0..8]);
+        bytes[8..12].copy_from_slice(&raw[12..16]);
+        bytes[16..20].copy_from_slice(&raw[8..12]);
+        let header: RecordHeader = unsafe { std::mem::transmute(bytes) };
+
+        let mut data = Vec::new();
+        self.reader().take(header.size as u64).read_to_end(&mut data)?;
+        if data.len() != header.size as usize {
+            return Err(Error::unexpected(start + 16, format!("{} bytes of {}", header.size, header.type_id), format!("{} bytes", data.len())))
+        }
+
+        let path = format!("{} at {:#x}", header.type_id, start);
+        let mut fields = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            if data.len() - pos < 8 {
+                return Err(Error::unexpected(pos as u64, "field header", format!("{} bytes", data.len() - pos)).located(start + 16, &path))
+            }
+            let type_id = FourCC([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+            let size = u32::from_le_bytes([data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]]) as usize;
+            pos += 8;
+            if data.len() - pos < size {
+                return Err(Error::unexpected(pos as u64, format!("{} bytes of {}", size, type_id), format!("{} bytes", data.len() - pos)).located(start + 16, &path))
+            }
+            fields.push(Field { type_id, data: Cow::Owned(data[pos..pos + size].to_vec()) });
+            pos += size;
+        }
+
+        Ok(Record { header, fields, compressed: None })
+    }
+}
+
+/// Null padded fixed size string.
+fn fixed_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    decode_string(&bytes[..end])
+}
@@ -0,0 +1,120 @@
+//! Streaming traversal of a plugin with a visitor.
+
+use crate::*;
+
+//------------------------------------------------------------------------------
+
+/// What to do after a visitor callback.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Walk {
+    /// Carry on into the group or record.
+    #[default]
+    Continue,
+    /// Skip the rest of the current group or record.
+    Skip,
+    /// End the walk.
+    Stop
+}
+
+/// Position of the walk when a callback is made.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkContext<'p> {
+    /// Number of groups enclosing the group or record.
+    pub depth: usize,
+    /// Groups from the top group down, including the group itself in group callbacks.
+    pub path: &'p [GroupKey],
+    /// Stream position of the group or record header.
+    pub offset: u64
+}
+
+/// Callbacks for `ESMParser2::walk`.
+///
+/// Every callback continues by default, so visitors only implement what they need.
+#[allow(unused_variables)]
+pub trait EsmVisitor {
+    /// A group header, before its contents. `Skip` seeks past the group
+    /// without calling `leave_group`.
+    fn enter_group(&mut self, header: &GroupHeader, context: &WalkContext) -> Walk { Walk::Continue }
+
+    /// The end of a group entered with `Continue`.
+    fn leave_group(&mut self, header: &GroupHeader, context: &WalkContext) -> Walk { Walk::Continue }
+
+    /// A record header, before its fields. `Skip` seeks past the record data,
+    /// so skipping records does not decompress them.
+    fn record(&mut self, header: &RecordHeader, context: &WalkContext) -> Walk { Walk::Continue }
+
+    /// A field of the last record. `Skip` ignores the remaining fields of the record.
+    fn field(&mut self, field: &Field, context: &WalkContext) -> Walk { Walk::Continue }
+}
+
+//------------------------------------------------------------------------------
+
+impl<'a, R> ESMParser2<R> where R: Source<'a> {
+    /// Walk every group, record and field of the plugin in file order,
+    /// without keeping any of them.
+    ///
+    /// Returns `Walk::Stop` if the visitor stopped the walk.
+    pub fn walk<V: EsmVisitor>(&mut self, visitor: &mut V) -> Result<Walk> {
+        let total_size = self.reader().seek(std::io::SeekFrom::End(0))?;
+        self.detect_game()?;
+
+        // end position and header of each open group
+        let mut groups: Vec<(u64, GroupHeader)> = Vec::new();
+        let mut path: Vec<GroupKey> = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            while let Some(&(end, header)) = groups.last() {
+                if end > offset { break }
+                let context = WalkContext { depth: groups.len() - 1, path: &path, offset };
+                if visitor.leave_group(&header, &context) == Walk::Stop { return Ok(Walk::Stop) }
+                groups.pop();
+                path.pop();
+            }
+            if offset >= total_size { break }
+
+            if self.peek_group_header()?.is_some() {
+                let header = self.read_group_header()?;
+                let end = self.group_end(&header)?;
+                path.push(GroupKey { group_type: group_type(&header), label: group_label(&header) });
+                let context = WalkContext { depth: groups.len(), path: &path, offset };
+                match visitor.enter_group(&header, &context) {
+                    Walk::Stop => return Ok(Walk::Stop),
+                    Walk::Skip => {
+                        path.pop();
+                        self.reader().seek(std::io::SeekFrom::Start(end))?;
+                    },
+                    Walk::Continue => groups.push((end, header))
+                }
+            } else {
+                let header = self.read_record_header()?;
+                let context = WalkContext { depth: groups.len(), path: &path, offset };
+                match visitor.record(&header, &context) {
+                    Walk::Stop => return Ok(Walk::Stop),
+                    Walk::Skip => { self.skip(header.size as u64)?; },
+                    Walk::Continue => {
+                        let start = self.reader().stream_position()?;
+                        let data = if header.flags & RECORD_FLAG_COMPRESSED != 0 {
+                            Cow::Owned(self.deflate(header.size)?)
+                        } else {
+                            self.reader().read_bytes(header.size as usize)?
+                        };
+                        let fields = parse_field_data(&data).map_err(|e| {
+                            e.located(start, &format!("{} at {:#x}", header.type_id, offset))
+                        })?;
+                        for field in &fields {
+                            match visitor.field(field, &context) {
+                                Walk::Stop => return Ok(Walk::Stop),
+                                Walk::Skip => break,
+                                Walk::Continue => {}
+                            }
+                        }
+                    }
+                }
+            }
+            offset = self.reader().stream_position()?;
+        }
+
+        Ok(Walk::Continue)
+    }
+}
@@ -0,0 +1,185 @@
+//! Writing plugins back to ESM bytes.
+
+use crate::*;
+
+use std::io::{Seek, SeekFrom};
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+//------------------------------------------------------------------------------
+
+/// Writes records and groups, recomputing record and group sizes.
+///
+/// Groups are written straight to the output and their sizes patched in
+/// afterwards, which is why the output must be seekable.
+///
+/// Records flagged as compressed are written from the data they were read
+/// with while their fields are unchanged, so writing an unmodified plugin
+/// parsed with `keep_compressed` gives the same bytes. Other compressed
+/// records are compressed again, or written uncompressed with the flag
+/// cleared unless `recompress` is set.
+pub struct EsmWriter<W: Write + Seek> {
+    out: W,
+    game: Game,
+    recompress: bool
+}
+
+impl<W: Write + Seek> EsmWriter<W> {
+    pub fn new(out: W) -> Self {
+        EsmWriter { out, game: Game::default(), recompress: false }
+    }
+
+    /// Game whose header layout is written, set from the file header by `write_plugin`.
+    pub fn game(mut self, game: Game) -> Self {
+        self.game = game;
+        self
+    }
+
+    /// Whether compressed records without their original data are compressed again.
+    pub fn recompress(mut self, recompress: bool) -> Self {
+        self.recompress = recompress;
+        self
+    }
+
+    /// The writer, flushed.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    /// Write a whole plugin.
+    pub fn write_plugin(&mut self, plugin: &Plugin) -> Result<()> {
+        self.game = plugin.file_header.game;
+        self.write_record(&plugin.header)?;
+        for (header, group) in &plugin.groups {
+            self.write_top_group(header, group)?;
+        }
+        Ok(())
+    }
+
+    /// Write a single record.
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        self.record(record)
+    }
+
+    /// Write a top group and everything nested in it.
+    pub fn write_top_group(&mut self, header: &GroupHeader, group: &TopGroup) -> Result<()> {
+        self.group(header, |writer| {
+            match group {
+                TopGroup::Unhandled(records) => writer.records(records),
+                TopGroup::GameSetting(settings) => settings.iter().try_for_each(|setting| writer.record(&setting.setting)),
+                TopGroup::Keyword(keywords) => keywords.iter().try_for_each(|keyword| writer.record(&keyword.keyword)),
+                TopGroup::Worldspace(worlds) => worlds.iter().try_for_each(|entry| writer.world_entry(entry)),
+                TopGroup::Cell(blocks) => blocks.iter().try_for_each(|block| {
+                    writer.group(&block.header, |writer| block.sub_blocks.iter().try_for_each(|sub_block| {
+                        writer.group(&sub_block.header, |writer| writer.cells(&sub_block.cells))
+                    }))
+                }),
+                TopGroup::Dialogue(dialogs) => dialogs.iter().try_for_each(|dialog| {
+                    writer.record(&dialog.dialog)?;
+                    dialog.children.as_ref().map_or(Ok(()), |children| writer.tree(children))
+                }),
+                TopGroup::Quest(quests) => quests.iter().try_for_each(|quest| {
+                    writer.record(&quest.quest)?;
+                    quest.children.as_ref().map_or(Ok(()), |children| writer.tree(children))
+                }),
+                _ => Err(Error::Unimplemented { offset: 0, path: format!("{:?}", header.get_label()), what: "writing a top group that was not parsed" })
+            }
+        })
+    }
+
+    //--------------------------------------------------------------------------
+
+    /// Write a group header, then whatever `body` writes, then go back and
+    /// fill in the group size.
+    fn group<F>(&mut self, header: &GroupHeader, body: F) -> Result<()>
+        where F: FnOnce(&mut Self) -> Result<()>
+    {
+        let start = self.out.stream_position()?;
+        let bytes: [u8; std::mem::size_of::<GroupHeader>()] = unsafe { std::mem::transmute(*header) };
+        self.out.write_all(&bytes[..self.game.header_size() as usize])?;
+        body(self)?;
+        let end = self.out.stream_position()?;
+        let size = u32::try_from(end - start)
+            .map_err(|_| Error::unexpected(start, "group under 4 GiB", format!("{} bytes", end - start)))?;
+        self.out.seek(SeekFrom::Start(start + 4))?;
+        self.out.write_all(&size.to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(end))?;
+        Ok(())
+    }
+
+    fn records(&mut self, records: &[Record]) -> Result<()> {
+        records.iter().try_for_each(|record| self.record(record))
+    }
+
+    fn record(&mut self, record: &Record) -> Result<()> {
+        let mut data = Vec::new();
+        for field in &record.fields { field.write_to(&mut data)?; }
+
+        let mut flags = record.header.flags;
+        if flags & RECORD_FLAG_COMPRESSED != 0 {
+            let unchanged = match &record.compressed {
+                Some(compressed) => inflate_record(compressed).is_ok_and(|inflated| inflated == data),
+                None => false
+            };
+            if unchanged {
+                data = record.compressed.as_deref().unwrap_or_default().to_vec();
+            } else if self.recompress {
+                let mut compressed = (data.len() as u32).to_le_bytes().to_vec();
+                let mut encoder = ZlibEncoder::new(&mut compressed, Compression::default());
+                encoder.write_all(&data)?;
+                encoder.finish()?;
+                data = compressed;
+            } else {
+                flags &= !RECORD_FLAG_COMPRESSED;
+            }
+        }
+
+        let mut bytes: [u8; std::mem::size_of::<RecordHeader>()] = unsafe { std::mem::transmute(record.header) };
+        bytes[4..8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes[8..12].copy_from_slice(&flags.to_le_bytes());
+        self.out.write_all(&bytes[..self.game.header_size() as usize])?;
+        self.out.write_all(&data)?;
+        Ok(())
+    }
+
+    /// A generic group tree, as parsed by `parse_group`.
+    fn tree(&mut self, group: &Group) -> Result<()> {
+        self.group(&group.header, |writer| group.children.iter().try_for_each(|child| match child {
+            GroupChild::Record(record) => writer.record(record),
+            GroupChild::Group(group) => writer.tree(group)
+        }))
+    }
+
+    fn world_entry(&mut self, entry: &WorldEntry) -> Result<()> {
+        self.record(&entry.world)?;
+        let Some(children) = &entry.world_children else { return Ok(()) };
+        self.group(&children.header, |writer| {
+            if let Some(road) = &children.road { writer.record(road)?; }
+            if let Some(cell) = &children.cell { writer.cell(cell)?; }
+            children.blocks.iter().try_for_each(|block| {
+                writer.group(&block.header, |writer| block.sub_blocks.iter().try_for_each(|sub_block| {
+                    writer.group(&sub_block.header, |writer| writer.cells(&sub_block.cells))
+                }))
+            })
+        })
+    }
+
+    fn cells(&mut self, cells: &[Cell]) -> Result<()> {
+        cells.iter().try_for_each(|cell| self.cell(cell))
+    }
+
+    fn cell(&mut self, cell: &Cell) -> Result<()> {
+        self.record(&cell.cell)?;
+        let Some(children) = &cell.cell_children else { return Ok(()) };
+        self.group(&children.header, |writer| children.subgroups.iter().try_for_each(|subgroup| {
+            let records = match subgroup.get_label() {
+                GroupLabel::CellPersistentChildren(_) => &children.persistant,
+                GroupLabel::CellTemporaryChildren(_) => &children.temporary,
+                _ => &children.visible_distant
+            };
+            writer.group(subgroup, |writer| writer.records(records.as_deref().unwrap_or_default()))
+        }))
+    }
+}